@@ -0,0 +1,154 @@
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+use std::fmt;
+
+/// Errors produced by the compression pipeline.
+#[derive(Debug)]
+pub enum CompressionError {
+    Io(std::io::Error),
+    Decode(String),
+    Encode(String),
+    Validation(Vec<String>),
+    UnsupportedFormat(String),
+    /// A settings file that can't be parsed
+    Config(String),
+    /// imagequant could not reach the minimum of the requested quality range
+    QualityTooLow,
+    #[allow(dead_code)]
+    Timeout,
+    #[allow(dead_code)]
+    Cancelled,
+}
+
+impl CompressionError {
+    fn error_type(&self) -> &'static str {
+        match self {
+            CompressionError::Io(_) => "Io",
+            CompressionError::Decode(_) => "Decode",
+            CompressionError::Encode(_) => "Encode",
+            CompressionError::Validation(_) => "Validation",
+            CompressionError::UnsupportedFormat(_) => "UnsupportedFormat",
+            CompressionError::Config(_) => "Config",
+            CompressionError::QualityTooLow => "QualityTooLow",
+            CompressionError::Timeout => "Timeout",
+            CompressionError::Cancelled => "Cancelled",
+        }
+    }
+
+    /// Machine-readable context for the frontend, `Null` when the message says it all.
+    fn details(&self) -> serde_json::Value {
+        match self {
+            CompressionError::Io(e) => serde_json::json!({ "kind": format!("{:?}", e.kind()) }),
+            CompressionError::Validation(errors) => serde_json::json!({ "errors": errors }),
+            CompressionError::UnsupportedFormat(format) => serde_json::json!({ "format": format }),
+            _ => serde_json::Value::Null,
+        }
+    }
+}
+
+impl fmt::Display for CompressionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompressionError::Io(e) => write!(f, "I/O error: {}", e),
+            CompressionError::Decode(msg) => write!(f, "Failed to decode image: {}", msg),
+            CompressionError::Encode(msg) => write!(f, "Failed to encode image: {}", msg),
+            CompressionError::Validation(errors) => write!(f, "Invalid options: {}", errors.join("; ")),
+            CompressionError::UnsupportedFormat(format) => write!(f, "Unsupported format: {}", format),
+            CompressionError::Config(msg) => write!(f, "Invalid config file: {}", msg),
+            CompressionError::QualityTooLow => write!(f, "Image can't be quantized within the requested quality"),
+            CompressionError::Timeout => write!(f, "Compression timed out"),
+            CompressionError::Cancelled => write!(f, "Compression was cancelled"),
+        }
+    }
+}
+
+/// Serialized as `{ "error_type", "message", "details"? }` so Tauri commands can return it as is.
+impl Serialize for CompressionError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let details = self.details();
+        let mut state = serializer.serialize_struct("CompressionError", 3)?;
+        state.serialize_field("error_type", self.error_type())?;
+        state.serialize_field("message", &self.to_string())?;
+        if details.is_null() {
+            state.skip_field("details")?;
+        } else {
+            state.serialize_field("details", &details)?;
+        }
+        state.end()
+    }
+}
+
+impl std::error::Error for CompressionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CompressionError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for CompressionError {
+    fn from(e: std::io::Error) -> Self {
+        CompressionError::Io(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn every_variant() -> Vec<CompressionError> {
+        vec![
+            CompressionError::Io(std::io::Error::new(std::io::ErrorKind::NotFound, "missing.png")),
+            CompressionError::Decode("truncated".to_string()),
+            CompressionError::Encode("truncated".to_string()),
+            CompressionError::Validation(vec!["truncated".to_string()]),
+            CompressionError::UnsupportedFormat("truncated".to_string()),
+            CompressionError::Config("truncated".to_string()),
+            CompressionError::QualityTooLow,
+            CompressionError::Timeout,
+            CompressionError::Cancelled,
+        ]
+    }
+
+    #[test]
+    fn every_variant_has_its_own_message() {
+        let messages: Vec<String> = every_variant().iter().map(|e| e.to_string()).collect();
+        let distinct: HashSet<&String> = messages.iter().collect();
+        assert_eq!(distinct.len(), messages.len(), "{:?}", messages);
+        assert!(messages.iter().all(|m| !m.is_empty() && !m.contains("CompressionError")));
+    }
+
+    #[test]
+    fn io_errors_convert_and_keep_their_source() {
+        fn read() -> Result<Vec<u8>, CompressionError> {
+            Ok(std::fs::read("/nonexistent/missing.png")?)
+        }
+        let error = read().unwrap_err();
+        assert!(matches!(&error, CompressionError::Io(e) if e.kind() == std::io::ErrorKind::NotFound));
+        assert!(error.to_string().starts_with("I/O error: "));
+        assert!(std::error::Error::source(&error).is_some());
+        assert!(std::error::Error::source(&CompressionError::Timeout).is_none());
+    }
+
+    #[test]
+    fn validation_error_serializes_with_its_details() {
+        let error = CompressionError::Validation(vec!["quality out of range".into()]);
+        assert_eq!(
+            serde_json::to_value(&error).unwrap(),
+            serde_json::json!({
+                "error_type": "Validation",
+                "message": "Invalid options: quality out of range",
+                "details": { "errors": ["quality out of range"] },
+            })
+        );
+    }
+
+    #[test]
+    fn errors_without_details_leave_the_field_out() {
+        assert_eq!(
+            serde_json::to_value(CompressionError::Cancelled).unwrap(),
+            serde_json::json!({ "error_type": "Cancelled", "message": "Compression was cancelled" })
+        );
+    }
+}
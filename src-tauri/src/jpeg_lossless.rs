@@ -0,0 +1,85 @@
+use mozjpeg_sys as ffi;
+use std::mem;
+use std::os::raw::{c_int, c_ulong, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+
+/// Rewrite a JPEG without touching its DCT coefficients, the way `jpegtran -optimize
+/// -progressive -copy icc` does: Huffman tables are rebuilt and the scans made progressive.
+/// Comment markers are dropped and the ICC profile (APP2) is kept. EXIF and XMP (APP1) are
+/// dropped unless `keep_exif` is set.
+pub fn optimize(data: &[u8], keep_exif: bool) -> Result<Vec<u8>, String> {
+    unsafe {
+        let mut src_err: ffi::jpeg_error_mgr = mem::zeroed();
+        let mut dst_err: ffi::jpeg_error_mgr = mem::zeroed();
+        let mut src: ffi::jpeg_decompress_struct = mem::zeroed();
+        let mut dst: ffi::jpeg_compress_struct = mem::zeroed();
+        src.common.err = unwinding_error_mgr(&mut src_err);
+        dst.common.err = unwinding_error_mgr(&mut dst_err);
+
+        let mut out_buffer: *mut u8 = ptr::null_mut();
+        let mut out_size: c_ulong = 0;
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            ffi::jpeg_create_decompress(&mut src);
+            ffi::jpeg_create_compress(&mut dst);
+            ffi::jpeg_mem_src(&mut src, data.as_ptr(), data.len() as c_ulong);
+            if keep_exif {
+                ffi::jpeg_save_markers(&mut src, ffi::jpeg_marker::APP0 as c_int + 1, 0xFFFF);
+            }
+            ffi::jpeg_save_markers(&mut src, ffi::jpeg_marker::APP0 as c_int + 2, 0xFFFF);
+            ffi::jpeg_read_header(&mut src, 1);
+
+            let coefficients = ffi::jpeg_read_coefficients(&mut src);
+            ffi::jpeg_copy_critical_parameters(&src, &mut dst);
+            dst.optimize_coding = 1;
+            ffi::jpeg_simple_progression(&mut dst);
+            ffi::jpeg_mem_dest(&mut dst, &mut out_buffer, &mut out_size);
+            ffi::jpeg_write_coefficients(&mut dst, coefficients);
+
+            // Saved markers have to be written after the coefficients start the output
+            let mut marker = src.marker_list;
+            while let Some(m) = marker.as_ref() {
+                ffi::jpeg_write_marker(&mut dst, m.marker as c_int, m.data, m.data_length);
+                marker = m.next;
+            }
+
+            ffi::jpeg_finish_compress(&mut dst);
+            ffi::jpeg_finish_decompress(&mut src);
+        }));
+        // Safe to call whatever state the error left them in
+        ffi::jpeg_destroy_compress(&mut dst);
+        ffi::jpeg_destroy_decompress(&mut src);
+
+        let output = match &result {
+            Ok(()) if !out_buffer.is_null() => slice::from_raw_parts(out_buffer, out_size as usize).to_vec(),
+            _ => Vec::new(),
+        };
+        if !out_buffer.is_null() {
+            libc::free(out_buffer as *mut c_void);
+        }
+        match result {
+            Ok(()) => Ok(output),
+            Err(e) => Err(e
+                .downcast_ref::<String>()
+                .cloned()
+                .unwrap_or_else(|| "libjpeg error".to_string())),
+        }
+    }
+}
+
+/// libjpeg's default error handler calls `exit()`, unwind back to `catch_unwind` instead.
+unsafe fn unwinding_error_mgr(err: &mut ffi::jpeg_error_mgr) -> &mut ffi::jpeg_error_mgr {
+    let err = ffi::jpeg_std_error(err);
+    err.error_exit = Some(unwind_error_exit);
+    err.emit_message = Some(silence_message);
+    err
+}
+
+extern "C-unwind" fn unwind_error_exit(cinfo: &mut ffi::jpeg_common_struct) {
+    let code = unsafe { cinfo.err.as_ref().map_or(0, |e| e.msg_code) };
+    // resume_unwind skips the panic hook, this is an expected error, not a bug
+    panic::resume_unwind(Box::new(format!("libjpeg error code {}", code)));
+}
+
+extern "C-unwind" fn silence_message(_cinfo: &mut ffi::jpeg_common_struct, _level: c_int) {}
@@ -1,21 +1,451 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+use base64::Engine;
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 
+use img_parts::ImageICC;
 use tauri::Emitter;
 use walkdir::WalkDir;
 
+mod error;
+mod jpeg_lossless;
+
+use error::CompressionError;
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default = "CompressionOptions::base")]
 struct CompressionOptions {
     mode: String,
     quality: u8,
+    /// What to do when the source path is a symlink: "follow", "skip" or "error"
+    symlink_strategy: String,
+    /// Lossless PNG: only strip the chunks known to be unsafe to keep, see `safe_chunk_names`
+    png_strip_unsafe_chunks_only: bool,
+    /// Lossless PNG: also strip the pHYs pixel density chunk
+    strip_phys: bool,
+    /// Record a SHA-256 of each source file in the result
+    hash_originals: bool,
+    /// How many times a failed metadata read, file read or write is retried, defaults to 0
+    max_retries: Option<u8>,
+    /// Delay before the first retry, doubled on each one after it (capped at 8x)
+    retry_delay_ms: u64,
+    /// Only back off on network filesystems, local paths retry after a fixed `retry_delay_ms`
+    backoff_network_only: bool,
+    /// Report the chroma subsampling of source JPEGs
+    detect_jpeg_subsampling: bool,
+    /// Lossy PNG: raise the quality range (up to 3 times) when the output is larger than the source
+    auto_quality_retry: bool,
+    /// Lossy PNG: relative weight of the R, G, B and A quantization error, e.g. [0.3, 0.6, 0.1, 1.0]
+    imagequant_weights: Option<[f32; 4]>,
+    /// What to do when the source file is read-only: "skip", "error" or "copy_then_compress"
+    readonly_strategy: String,
+    /// Time each file and emit a "benchmark-result" event once the batch is done
+    benchmark_mode: BenchmarkMode,
+    /// Lossy PNG: when imagequant can't reach the minimum quality, retry (up to 3 times)
+    /// with no minimum and a maximum 20 lower each time, down to 20
+    retry_on_encode_error_with_lower_quality: bool,
+    /// Convert to "webp" or "avif" when that is smaller than the source, "png" or "jpg"
+    /// always convert. The new file is written next to the source, which is kept
+    output_format: Option<String>,
+    /// oxipng preset, 1 (fastest) to 6 (smallest output). Defaults to 2
+    png_effort: Option<u8>,
+    /// Run the whole pipeline but write nothing, results get the "preview" status
+    dry_run: bool,
+    /// Write results into this directory (created if missing) instead of over the source
+    output_dir: Option<String>,
+    /// Copy the source to `<path>.orig` before overwriting it, see `restore_backup`. An existing
+    /// `.orig` from an earlier run is kept, it holds the original
+    backup: bool,
+    /// PNG chunk stripping: "none", "safe" or "all". When unset lossless strips the safe set
+    /// (see `lossless_strip_chunks`) and lossy strips everything. Lossy with "none" or "safe"
+    /// carries the source color chunks (iCCP, sRGB, gAMA, cHRM) over to the quantized image
+    strip_mode: Option<String>,
+    /// Lossy PNG and GIF: imagequant dithering level from 0.0 to 1.0, defaults to 1.0.
+    /// 0.0 disables dithering entirely, which is what sprites, pixel art and icons want
+    dithering: Option<f32>,
+    /// Lossy JPEG chroma subsampling: "4:4:4", "4:2:2" or "4:2:0", defaults to 4:2:0.
+    /// 4:4:4 keeps colored text and thin lines in screenshots and diagrams sharp
+    jpeg_subsampling: Option<String>,
+    /// Lossy JPEG: write a progressive JPEG (single DC scan for all components) instead of baseline
+    jpeg_progressive: bool,
+    /// Give written files the source's modification time instead of the time of the write
+    preserve_timestamps: bool,
+    /// PNG: store 8-bit RGBA images whose alpha is 255 everywhere as RGB before quantizing
+    /// or optimizing. Not applied to APNGs, only their first frame is decoded
+    color_downgrade: bool,
+    /// Compare the compressed image against the source and report the SSIM as `quality_score`
+    compute_quality_metric: bool,
+    /// `compress_files`: number of worker threads, defaults to one per logical CPU.
+    /// Lower it to keep laptops from throttling on big batches
+    concurrency: Option<usize>,
+    /// `compress_files`: append the batch's results to this file as one JSON line,
+    /// `{ "timestamp": <unix seconds>, "results": [...] }`
+    log_path: Option<String>,
+    /// Leave files alone unless compression saves at least this many bytes, defaults to 0
+    min_size_saving_bytes: Option<u64>,
+    /// Skip files last modified before this Unix timestamp (seconds), for incremental runs
+    skip_if_modified_before: Option<u64>,
+    /// Lossy PNG quantizes 8-bit RGBA, so 16-bit sources are kept lossless unless this is set.
+    /// The lossless pipeline always keeps the bit depth
+    allow_bit_depth_reduction: bool,
+    /// Skip files larger than this, huge uncompressed images can take minutes
+    max_file_bytes: Option<u64>,
+    /// Skip files smaller than this, tiny icons rarely have anything left to gain
+    min_file_bytes: Option<u64>,
+    /// Rotate and flip pixels by the source's EXIF orientation whenever they are re-encoded.
+    /// Re-encoded output carries no EXIF, so the orientation isn't applied twice
+    auto_orient: bool,
+    /// Lossy PNG and GIF: most colors imagequant may use, 2 to 256 (the default). A small
+    /// palette that can't reach the minimum quality falls back like any other quality failure
+    palette_size: Option<u16>,
+    /// Lossy JPEG: pick the highest quality from `quality` up to 95 whose output fits in this
+    /// many bytes. Files that don't fit even at `quality` are skipped
+    target_size_bytes: Option<u64>,
+    /// PNG output: `true` writes Adam7 interlaced PNGs, `false` de-interlaces. Unset keeps
+    /// oxipng's default
+    png_interlace: Option<bool>,
+    /// Lossy PNG: carry the source's ICC profile (iCCP) over to the quantized image, which
+    /// otherwise keeps no ancillary chunks
+    preserve_icc: bool,
+    /// Lossless PNG: store 8-bit RGB(A) images whose pixels all have R == G == B as grayscale,
+    /// keeping alpha only when some pixel is transparent. Not applied to APNGs
+    allow_grayscale_conversion: bool,
+    /// PNG output: add "Software" and "CompressionMode" tEXt chunks naming this tool and
+    /// `mode`. Skipped when `strip_mode` is "all"
+    tag_output: bool,
+    /// `compress_files`: send at most one "compression-progress" event per this many
+    /// milliseconds. The last file's event is always sent. Unset sends one per file
+    progress_throttle_ms: Option<u64>,
+    /// Unix: give written files the source's permission bits. Files replaced in place already
+    /// keep theirs, this matters for new files (converted formats, `output_dir`)
+    preserve_permissions: bool,
+    /// Replace files in place by writing a temp file next to them and renaming it over the
+    /// original, so a failed write never truncates the source. On by default, turn it off
+    /// where renaming over an existing file isn't atomic
+    atomic_write: bool,
+    /// `compress_files`: "size_desc" or "size_asc" starts the batch with the largest or the
+    /// smallest files, "input_order" (the default) keeps the given order. Files run in
+    /// parallel, so this is the order work is handed out, not the order it finishes
+    processing_order: Option<String>,
+    /// libwebp method, 0 (fastest) to 6 (smallest output), for WebP output in both modes.
+    /// Defaults to 4
+    webp_method: Option<u8>,
+    /// `compress_files`: cancel the rest of the batch after the first failed file. Files
+    /// already being compressed still finish
+    abort_on_error: bool,
+    /// JPEG: only drop metadata segments (EXIF, XMP, ICC, comments and other APPn markers),
+    /// the compressed image data is copied as is whatever `mode` says. JFIF (APP0) and Adobe
+    /// (APP14) headers are kept as they affect decoding, and so is EXIF carrying an orientation
+    jpeg_strip_only: bool,
+    /// `compress_files`: work through the paths in chunks of this many files, one chunk at a
+    /// time, to bound how many images are decoded at once. A "compression-batch-complete"
+    /// event follows each chunk
+    batch_size: Option<usize>,
+    /// JPEG to JPEG: copy the source EXIF into the output with every GPS field left out,
+    /// whichever pipeline ran, so camera, copyright and date survive without the location.
+    /// The orientation is reset to 1 when `auto_orient` already turned the pixels upright
+    strip_gps_exif: bool,
+    /// With `output_dir`, recreate each file's directories below the common parent of the
+    /// batch instead of writing every file straight into `output_dir`. Only the batch
+    /// commands know that parent, single files still go straight into `output_dir`
+    mirror_directory_structure: bool,
+    /// The batch's common parent for `mirror_directory_structure`, filled in by the batch
+    /// commands once they have created the output directories
+    #[serde(skip)]
+    mirror_root: Option<PathBuf>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+impl Default for CompressionOptions {
+    fn default() -> Self {
+        CompressionOptions {
+            mode: "lossless".to_string(),
+            quality: 75,
+            // Following a symlink overwrites the link target, so don't do it unless asked
+            symlink_strategy: "skip".to_string(),
+            png_strip_unsafe_chunks_only: false,
+            strip_phys: false,
+            hash_originals: false,
+            max_retries: None,
+            retry_delay_ms: 100,
+            backoff_network_only: false,
+            detect_jpeg_subsampling: false,
+            auto_quality_retry: false,
+            imagequant_weights: None,
+            readonly_strategy: "error".to_string(),
+            benchmark_mode: BenchmarkMode::Off,
+            retry_on_encode_error_with_lower_quality: false,
+            output_format: None,
+            png_effort: None,
+            dry_run: false,
+            output_dir: None,
+            backup: false,
+            strip_mode: None,
+            dithering: None,
+            jpeg_subsampling: None,
+            jpeg_progressive: false,
+            preserve_timestamps: false,
+            color_downgrade: false,
+            compute_quality_metric: false,
+            concurrency: None,
+            log_path: None,
+            min_size_saving_bytes: None,
+            skip_if_modified_before: None,
+            allow_bit_depth_reduction: false,
+            max_file_bytes: None,
+            min_file_bytes: None,
+            auto_orient: false,
+            palette_size: None,
+            target_size_bytes: None,
+            png_interlace: None,
+            preserve_icc: false,
+            allow_grayscale_conversion: false,
+            tag_output: false,
+            progress_throttle_ms: None,
+            preserve_permissions: false,
+            atomic_write: true,
+            processing_order: None,
+            webp_method: None,
+            abort_on_error: false,
+            jpeg_strip_only: false,
+            batch_size: None,
+            strip_gps_exif: false,
+            mirror_directory_structure: false,
+            mirror_root: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+enum BenchmarkMode {
+    /// Normal compression, nothing is timed
+    #[default]
+    Off,
+    /// Only collect timings, sizes are not reported and nothing is written
+    TimingOnly,
+    /// Timings and sizes, but nothing is written (a timed dry run)
+    Full,
+    /// Timings and sizes of a normal run that writes its output
+    WithWrite,
+}
+
+impl BenchmarkMode {
+    fn writes_output(self) -> bool {
+        matches!(self, BenchmarkMode::Off | BenchmarkMode::WithWrite)
+    }
+
+    fn reports_size(self) -> bool {
+        self != BenchmarkMode::TimingOnly
+    }
+}
+
+impl CompressionOptions {
+    /// oxipng settings for the configured `png_effort` and `png_interlace`.
+    fn oxipng_options(&self) -> oxipng::Options {
+        let mut oxi_options = oxipng::Options::from_preset(self.png_effort.unwrap_or(2));
+        match self.png_interlace {
+            Some(true) => oxi_options.interlace = Some(oxipng::Interlacing::Adam7),
+            Some(false) => oxi_options.interlace = Some(oxipng::Interlacing::None),
+            None => {}
+        }
+        oxi_options
+    }
+
+    /// The format `output_format` asks for.
+    fn output_image_format(&self) -> Option<image::ImageFormat> {
+        match self.output_format.as_deref() {
+            Some("webp") => Some(image::ImageFormat::WebP),
+            Some("avif") => Some(image::ImageFormat::Avif),
+            Some("png") => Some(image::ImageFormat::Png),
+            Some("jpg" | "jpeg") => Some(image::ImageFormat::Jpeg),
+            _ => None,
+        }
+    }
+
+    /// The chunks to strip when `strip_mode` is set explicitly.
+    fn strip_chunks(&self) -> Option<oxipng::StripChunks> {
+        match self.strip_mode.as_deref() {
+            Some("none") => Some(oxipng::StripChunks::None),
+            Some("safe") => Some(oxipng::StripChunks::Safe),
+            Some("all") => Some(oxipng::StripChunks::All),
+            _ => None,
+        }
+    }
+
+    /// Check the values the pipeline can't recover from, listing every problem at once.
+    fn validate(&self) -> Result<(), CompressionError> {
+        let mut errors = Vec::new();
+        if !["lossy", "lossless", "auto"].contains(&self.mode.as_str()) {
+            errors.push(format!("unknown mode '{}'", self.mode));
+        }
+        if self.quality > 100 {
+            errors.push(format!("quality {} out of range 0-100", self.quality));
+        }
+        if !["follow", "skip", "error"].contains(&self.symlink_strategy.as_str()) {
+            errors.push(format!("unknown symlink_strategy '{}'", self.symlink_strategy));
+        }
+        if !["skip", "error", "copy_then_compress"].contains(&self.readonly_strategy.as_str()) {
+            errors.push(format!("unknown readonly_strategy '{}'", self.readonly_strategy));
+        }
+        if let Some(dithering) = self.dithering {
+            if !(0.0..=1.0).contains(&dithering) {
+                errors.push(format!("dithering {} out of range 0.0-1.0", dithering));
+            }
+        }
+        if let Some(mode) = &self.strip_mode {
+            if !["none", "safe", "all"].contains(&mode.as_str()) {
+                errors.push(format!("unknown strip_mode '{}'", mode));
+            }
+        }
+        if let Some(subsampling) = &self.jpeg_subsampling {
+            if !["4:4:4", "4:2:2", "4:2:0"].contains(&subsampling.as_str()) {
+                errors.push(format!("unknown jpeg_subsampling '{}'", subsampling));
+            }
+        }
+        if let Some(order) = &self.processing_order {
+            if !["size_desc", "size_asc", "input_order"].contains(&order.as_str()) {
+                errors.push(format!("unknown processing_order '{}'", order));
+            }
+        }
+        if self.mirror_directory_structure && self.output_dir.is_none() {
+            errors.push("mirror_directory_structure needs an output_dir".to_string());
+        }
+        if self.batch_size == Some(0) {
+            errors.push("batch_size must be at least 1".to_string());
+        }
+        if self.concurrency == Some(0) {
+            errors.push("concurrency must be at least 1".to_string());
+        }
+        if let (Some(min), Some(max)) = (self.min_file_bytes, self.max_file_bytes) {
+            if min > max {
+                errors.push(format!("min_file_bytes {} is larger than max_file_bytes {}", min, max));
+            }
+        }
+        if let Some(size) = self.palette_size {
+            if !(2..=256).contains(&size) {
+                errors.push(format!("palette_size {} out of range 2-256", size));
+            }
+        }
+        if let Some(method) = self.webp_method {
+            if method > 6 {
+                errors.push(format!("webp_method {} out of range 0-6", method));
+            }
+        }
+        if let Some(effort) = self.png_effort {
+            if !(1..=6).contains(&effort) {
+                errors.push(format!("png_effort {} out of range 1-6", effort));
+            }
+        }
+        if let Some(format) = &self.output_format {
+            if !["webp", "avif", "png", "jpg", "jpeg"].contains(&format.as_str()) {
+                errors.push(format!("unsupported output_format '{}'", format));
+            }
+        }
+        if let Some(weights) = self.imagequant_weights {
+            if weights.iter().any(|w| !w.is_finite() || *w <= 0.0) {
+                errors.push(format!("imagequant_weights {:?} must all be positive", weights));
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(CompressionError::Validation(errors))
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum ConversionSeverity {
+    Error,
+    Warning,
+}
+
+/// A problem with one key while building `CompressionOptions` from a key-value map.
+#[derive(Serialize, Debug, Clone)]
+struct ConversionError {
+    key: String,
+    message: String,
+    severity: ConversionSeverity,
+}
+
+/// `CompressionOptions` built from a key-value map, with the warnings for the keys it ignored.
+#[derive(Serialize, Debug, Clone)]
+struct ConvertedOptions {
+    options: CompressionOptions,
+    warnings: Vec<ConversionError>,
+}
+
+/// Build options from a partial key-value map, every missing key keeps its default.
+/// Unknown keys are reported as warnings, invalid values as errors.
+impl TryFrom<HashMap<String, serde_json::Value>> for ConvertedOptions {
+    type Error = Vec<ConversionError>;
+
+    fn try_from(values: HashMap<String, serde_json::Value>) -> Result<Self, Self::Error> {
+        let defaults = match serde_json::to_value(CompressionOptions::base()) {
+            Ok(serde_json::Value::Object(map)) => map,
+            _ => serde_json::Map::new(),
+        };
+        let mut merged = defaults.clone();
+        let mut errors: Vec<ConversionError> = Vec::new();
+
+        // Sorted so the error list is stable between calls
+        let values: BTreeMap<String, serde_json::Value> = values.into_iter().collect();
+        for (key, value) in values {
+            if !defaults.contains_key(&key) {
+                errors.push(ConversionError {
+                    message: format!("Unknown option '{}' ignored", key),
+                    key,
+                    severity: ConversionSeverity::Warning,
+                });
+                continue;
+            }
+            // Check each value against the defaults on its own so every bad key is reported
+            let mut single = defaults.clone();
+            single.insert(key.clone(), value.clone());
+            match serde_json::from_value::<CompressionOptions>(serde_json::Value::Object(single)) {
+                Ok(_) => {
+                    merged.insert(key, value);
+                }
+                Err(e) => errors.push(ConversionError {
+                    message: format!("Invalid value for '{}': {}", key, e),
+                    key,
+                    severity: ConversionSeverity::Error,
+                }),
+            }
+        }
+
+        if errors.iter().any(|e| e.severity == ConversionSeverity::Error) {
+            return Err(errors);
+        }
+        let options = serde_json::from_value(serde_json::Value::Object(merged)).map_err(|e| {
+            vec![ConversionError {
+                key: String::new(),
+                message: e.to_string(),
+                severity: ConversionSeverity::Error,
+            }]
+        })?;
+        Ok(ConvertedOptions { options, warnings: errors })
+    }
+}
+
+/// Same as `ConvertedOptions`, for callers that don't report the warnings.
+impl TryFrom<HashMap<String, serde_json::Value>> for CompressionOptions {
+    type Error = Vec<ConversionError>;
+
+    fn try_from(values: HashMap<String, serde_json::Value>) -> Result<Self, Self::Error> {
+        ConvertedOptions::try_from(values).map(|converted| converted.options)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 #[serde(rename_all = "camelCase")]
 struct CompressionResult {
     file_path: String,
@@ -24,292 +454,4651 @@ struct CompressionResult {
     saved_before: u64,
     status: String,
     error: Option<String>,
+    notes: Vec<String>,
+    /// Hex SHA-256 of the source bytes, set when `hash_originals` is enabled
+    original_file_hash: Option<String>,
+    /// `pixel_hash` of the source, set with `hash_originals` in lossless mode when the output
+    /// format can hold the exact pixels, for `validate_output`
+    original_pixel_hash: Option<String>,
+    /// Chroma subsampling of a source JPEG ("4:4:4", "4:2:2", "4:2:0", ...)
+    source_chroma_subsampling: Option<String>,
+    /// Time spent in the compression pipeline, set when benchmarking
+    compression_time_ms: Option<u64>,
+    /// Wall time `process_single_file` spent on the file, reading and writing included
+    duration_ms: Option<u64>,
+    /// Format of the file on disk after compression ("png", "jpg", "webp")
+    output_format: Option<String>,
+    /// Source file when the result was written elsewhere (`output_dir`, or a conversion in
+    /// place), `file_path` is the output
+    source_path: Option<String>,
+    /// Copy of the source made before it was overwritten
+    backup_path: Option<String>,
+    /// Number of frames of a source GIF, more than 1 when animated
+    frame_count: Option<u32>,
+    /// `compressed_size / original_size`, 1.0 for files that were left as they were
+    compression_ratio: f64,
+    /// SSIM of the compressed luma against the source, 1.0 is identical.
+    /// Set with `compute_quality_metric` when the compressed file was smaller
+    quality_score: Option<f64>,
+    /// Source dimensions, from the image header so no pipeline has to decode for them
+    width: Option<u32>,
+    height: Option<u32>,
+    /// Estimated memory for the decoded pixels: width x height x 4 (RGBA8), per frame for GIFs
+    peak_memory_bytes: Option<u64>,
+}
+
+/// Totals for a `compress_files` batch, sent as the "compression-complete" event.
+#[derive(Serialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+struct CompressionSummary {
+    total_files: usize,
+    /// "success" and "preview" results
+    succeeded: usize,
+    failed: usize,
+    /// Skipped and cancelled files
+    skipped: usize,
+    total_original_bytes: u64,
+    total_compressed_bytes: u64,
+    total_saved_bytes: u64,
+    /// `abort_on_error` stopped the batch, the files after it were cancelled
+    aborted_due_to_error: bool,
+    /// The failure that stopped the batch
+    first_error_path: Option<String>,
+}
+
+impl CompressionSummary {
+    fn from_results(results: &[CompressionResult]) -> Self {
+        let mut summary = CompressionSummary {
+            total_files: results.len(),
+            ..Default::default()
+        };
+        for result in results {
+            match result.status.as_str() {
+                "success" | "preview" => summary.succeeded += 1,
+                "error" => summary.failed += 1,
+                _ => summary.skipped += 1,
+            }
+            summary.total_original_bytes += result.original_size;
+            summary.total_compressed_bytes += result.compressed_size;
+            summary.total_saved_bytes += result.saved_before;
+        }
+        summary
+    }
+}
+
+/// Payload of the "benchmark-result" event sent at the end of a benchmarked batch.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct BenchmarkReport {
+    mode: BenchmarkMode,
+    file_count: usize,
+    /// Wall clock time of the whole batch
+    total_time_ms: u64,
+    /// Sum of the per-file pipeline times, larger than the total when files run in parallel
+    compression_time_ms: u64,
+    files_per_second: f64,
+    original_bytes: u64,
+    /// `None` in `TimingOnly` mode
+    compressed_bytes: Option<u64>,
+}
+
+impl BenchmarkReport {
+    fn from_results(mode: BenchmarkMode, results: &[CompressionResult], elapsed: std::time::Duration) -> Self {
+        let timed: Vec<&CompressionResult> = results.iter().filter(|r| r.compression_time_ms.is_some()).collect();
+        let secs = elapsed.as_secs_f64();
+        BenchmarkReport {
+            mode,
+            file_count: timed.len(),
+            total_time_ms: elapsed.as_millis() as u64,
+            compression_time_ms: timed.iter().filter_map(|r| r.compression_time_ms).sum(),
+            files_per_second: if secs > 0.0 { timed.len() as f64 / secs } else { 0.0 },
+            original_bytes: timed.iter().map(|r| r.original_size).sum(),
+            compressed_bytes: mode.reports_size().then(|| timed.iter().map(|r| r.compressed_size).sum()),
+        }
+    }
+}
+
+/// Outcome of re-checking compressed files on disk, invalid entries are `(path, error)`.
+#[derive(Serialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+struct ValidationReport {
+    valid: Vec<String>,
+    invalid: Vec<(String, String)>,
+}
+
+/// Integrity of a source file, see `validate_files`.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct ValidationResult {
+    file_path: String,
+    valid: bool,
+    error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct ImageAnalysis {
+    format: String,
+    width: u32,
+    height: u32,
+    has_icc_profile: bool,
+    /// ASCII description from the profile's `desc` tag, e.g. "Adobe RGB (1998)"
+    icc_profile_name: Option<String>,
+    icc_profile_size_bytes: u32,
+}
+
+/// Sizes `compare_compression_modes` found for one file. A mode that can't shrink the
+/// file reports the original size.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct ComparisonResult {
+    original_size: u64,
+    lossless_size: u64,
+    lossy_size: u64,
+    /// SSIM of the lossy output, `None` when it wasn't smaller or can't be decoded
+    lossy_ssim: Option<f64>,
+}
+
+/// Lowest lossy PNG quality `find_optimal_png_quality` found to reach the target SSIM.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct OptimalQualityResult {
+    quality: u8,
+    achieved_ssim: f64,
+    compressed_size: u64,
+}
+
+/// How far `compare_files` found the compressed pixels from the original, over RGBA channels.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct PixelDiffResult {
+    max_channel_delta: u8,
+    mean_absolute_error: f64,
+    /// Pixels with at least one channel that differs
+    changed_pixel_count: u32,
+    total_pixels: u32,
+}
+
+/// A chunk of a PNG file, `size` is the length of its data.
+#[derive(Serialize, Debug, Clone)]
+struct ChunkInfo {
+    name: String,
+    size: u32,
+}
+
+/// Header metadata of an image file. `color_type` is one of "gray", "gray_alpha", "rgb",
+/// "rgba" or "indexed", and `bit_depth` is per channel (per index for "indexed").
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct FileInfo {
+    path: String,
+    file_size: u64,
+    width: u32,
+    height: u32,
+    color_type: String,
+    bit_depth: u8,
+    has_alpha: bool,
+    format: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct SavingsEstimate {
+    estimated_total_savings_bytes: u64,
+    estimated_savings_percent: f32,
+    /// Half-width of the 95% confidence interval around `estimated_savings_percent`
+    confidence_interval_percent: f32,
+    sample_size: usize,
+}
+
+/// What an oxipng preset does, in the words `preview_oxipng_options` shows the user.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct OxipngOptionsInfo {
+    preset: u8,
+    /// Row filters oxipng tries, keeping the smallest result
+    filters: Vec<String>,
+    deflate: String,
+    interlace: String,
+    strip: String,
 }
 
+/// `get_compression_estimate`'s extrapolation over all the given files.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct EstimateResult {
+    total_files: usize,
+    estimated_savings_bytes: i64,
+    estimated_savings_pct: f64,
+}
+
+/// Filters applied by `scan_paths_with_options` and `scan_paths_streaming`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+struct ScanOptions {
+    min_width: Option<u32>,
+    max_width: Option<u32>,
+    min_height: Option<u32>,
+    max_height: Option<u32>,
+    /// Streaming scan: files queued for the frontend before the walk waits for it to catch up
+    scan_buffer_size: usize,
+    /// Walk into symlinked folders and pick up symlinked files, see `walk_image_paths`
+    follow_symlinks: bool,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        ScanOptions {
+            min_width: None,
+            max_width: None,
+            min_height: None,
+            max_height: None,
+            scan_buffer_size: 1000,
+            follow_symlinks: false,
+        }
+    }
+}
+
+impl ScanOptions {
+    fn dimensions_match(&self, width: u32, height: u32) -> bool {
+        self.min_width.is_none_or(|min| width >= min)
+            && self.max_width.is_none_or(|max| width <= max)
+            && self.min_height.is_none_or(|min| height >= min)
+            && self.max_height.is_none_or(|max| height <= max)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct ScannedFile {
+    path: String,
+    /// File size in bytes
+    size: u64,
+    /// Last modification time as a Unix timestamp, 0 when the platform doesn't report it
+    modified: u64,
+    width: Option<u32>,
+    height: Option<u32>,
+    warning: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+struct ScanResult {
+    files: Vec<ScannedFile>,
+    excluded_by_dimension: usize,
+}
+
+/// Maximum number of colors an indexed PNG palette can hold.
+const PALETTE_MAX_COLORS: usize = 256;
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
 struct ProgressEvent {
     done: usize,
     total: usize,
     result: CompressionResult,
+    /// Source bytes of the files processed so far over the time since the batch started
+    bytes_per_second: f64,
 }
 
-#[tauri::command]
-async fn scan_paths(paths: Vec<String>) -> Vec<String> {
-    let mut results: Vec<String> = Vec::new();
-    let supported_extensions = vec!["jpg", "jpeg", "png"];
+/// Sent as "compression-started" before the first file of a `compress_files` batch, in batch order.
+#[derive(Serialize, Debug, Clone)]
+struct CompressionStartedEvent {
+    total: usize,
+    paths: Vec<String>,
+}
 
-    for p in paths {
-        let path = Path::new(&p);
-        if path.is_dir() {
-            for entry in WalkDir::new(path).into_iter().filter_map(|e| e.ok()) {
-                if entry.file_type().is_file() {
-                    let ext = entry
-                        .path()
-                        .extension()
-                        .and_then(|s| s.to_str())
-                        .unwrap_or("")
-                        .to_lowercase();
-                    if supported_extensions.contains(&ext.as_str()) {
-                        results.push(entry.path().to_string_lossy().to_string());
-                    }
-                }
-            }
-        } else if path.is_file() {
-            let ext = path
-                .extension()
-                .and_then(|s| s.to_str())
-                .unwrap_or("")
-                .to_lowercase();
-            if supported_extensions.contains(&ext.as_str()) {
-                results.push(path.to_string_lossy().to_string());
-            }
-        }
-    }
-    results
+/// Sent as "compression-directory-complete" once every file of `directory` in a batch is done.
+#[derive(Serialize, Debug, Clone)]
+struct DirectoryProgressEvent {
+    directory: String,
+    done: usize,
+    total: usize,
 }
 
-#[tauri::command]
-async fn compress_files(
-    app: tauri::AppHandle,
-    paths: Vec<String>,
+/// Sent as "compression-batch-complete" after each `batch_size` chunk of a batch, `index`
+/// counts from 0.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct BatchChunkEvent {
+    index: usize,
+    chunk_count: usize,
+    done: usize,
+    total: usize,
+}
+
+/// Set by `cancel_compression`, checked before each file of a `compress_files` batch.
+#[derive(Default)]
+struct CancellationToken(Arc<AtomicBool>);
+
+/// Active `watch_directory` watchers by directory. Dropping a watcher stops it, which also
+/// ends its compression thread.
+#[derive(Default)]
+struct DirectoryWatchers(Mutex<HashMap<String, notify::RecommendedWatcher>>);
+
+/// How long a watched file has to stay quiet before it is compressed. A file being copied in
+/// shows up as a Create followed by a burst of Modify events.
+const WATCH_DEBOUNCE: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// Compresses files with a fixed set of options.
+struct FileProcessor {
     options: CompressionOptions,
-) -> Result<(), String> {
-    let total = paths.len();
-    let done = Arc::new(Mutex::new(0));
+}
 
-    // Process files in parallel using rayon
-    paths.par_iter().for_each(|file_path| {
-        let path = Path::new(file_path);
-        let result = process_single_file(path, &options);
-
-        let mut done_lock = done.lock().unwrap();
-        *done_lock += 1;
-        let current_done = *done_lock;
-
-        // Emit progress event
-        // Note: Generic error handling here because Emitter can fail if window is closed
-        let _ = app.emit(
-            "compression-progress",
-            ProgressEvent {
-                done: current_done,
-                total,
-                result: result.clone(),
-            },
-        );
-    });
+impl FileProcessor {
+    fn new(options: CompressionOptions) -> Self {
+        FileProcessor { options }
+    }
 
-    Ok(())
+    fn process(&self, path: &Path) -> CompressionResult {
+        process_single_file(path, &self.options)
+    }
+
+    /// Lazily compress files one at a time, for streaming or low-memory callers.
+    /// Nothing is processed until the returned iterator is advanced.
+    #[allow(dead_code)]
+    fn process_batch<I: Iterator<Item = PathBuf>>(self, paths: I) -> impl Iterator<Item = CompressionResult> {
+        paths.map(move |path| self.process(&path))
+    }
+
+    /// Compress all files on a dedicated rayon pool with `num_threads` workers.
+    #[allow(dead_code)]
+    fn process_batch_parallel(self, paths: Vec<PathBuf>, num_threads: usize) -> Vec<CompressionResult> {
+        run_in_pool(Some(num_threads), || paths.par_iter().map(|path| self.process(path)).collect())
+    }
 }
 
-fn process_single_file(path: &Path, options: &CompressionOptions) -> CompressionResult {
-    let file_path_str = path.to_string_lossy().to_string();
-    
-    // Get original size
-    let metadata = match fs::metadata(path) {
-        Ok(m) => m,
-        Err(e) => return create_error_result(&file_path_str, &e.to_string()),
+/// Run `op` on a dedicated rayon pool with `num_threads` workers, or on the global pool when
+/// that is `None` or the pool can't be built.
+fn run_in_pool<R: Send>(num_threads: Option<usize>, op: impl FnOnce() -> R + Send) -> R {
+    let Some(num_threads) = num_threads else {
+        return op();
     };
-    let original_size = metadata.len();
-    let ext = path.extension().and_then(|s| s.to_str()).unwrap_or("").to_lowercase();
-    let mut compressed_data: Vec<u8> = Vec::new();
+    match rayon::ThreadPoolBuilder::new().num_threads(num_threads).build() {
+        Ok(pool) => pool.install(op),
+        Err(e) => {
+            println!("[Compress] Failed to build thread pool: {}, using the global pool.", e);
+            op()
+        }
+    }
+}
 
-    // Compression Logic
-    let compression_result = match ext.as_str() {
-        "png" => {
-            let input_data = match fs::read(path) {
-                Ok(d) => d,
-                Err(e) => return create_error_result(&file_path_str, &format!("Failed to read file: {}", e)),
-            };
+/// File extensions picked up when scanning folders.
+/// AVIF sources are found but skipped with "skipped_unsupported", there is no AVIF decoder
+/// in this build.
+const SUPPORTED_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "gif", "avif", "tif", "tiff", "bmp"];
 
-            println!("DEBUG: Processing mode={}, quality={}", options.mode, options.quality);
-            if options.mode == "lossy" {
-                // Advanced Lossy Compression: imagequant -> png crate -> oxipng
-                println!("DEBUG: Starting lossy compression pipeline...");
-                // 1. Load and prepare image
-                let img = match image::open(path) {
-                    Ok(i) => i,
-                    Err(e) => return create_error_result(&file_path_str, &format!("Failed to open image: {}", e)),
-                };
-                let img_rgba = img.to_rgba8();
-                let width = img.width() as usize;
-                let height = img.height() as usize;
-
-                // 2. Quantize
-                let quant_result = (|| -> Result<Vec<u8>, String> {
-                    let mut attr = imagequant::Attributes::new();
-                    let q = options.quality;
-                    // Quality range logic: 
-                    // To give users more control:
-                    // High quality (e.g. 90) -> min 60. Forces algorithm to fail if visual loss is too high.
-                    // Low quality (e.g. 40) -> min 0. Allows heavy compression.
-                    // This makes the slider feel more responsive to "quality requirements".
-                    let min_q = if q > 30 { q - 30 } else { 0 };
-                    attr.set_quality(min_q, q).map_err(|e| format!("IQ Quality err: {:?}", e))?;
-                    
-                    println!("DEBUG: ImageQuant config quality={}-{}", min_q, q);
-
-                    // Convert raw bytes to RGBA slice using bytemuck
-                    let rgba_slice: &[rgb::RGBA8] = bytemuck::cast_slice(img_rgba.as_raw());
-                    
-                    let mut iq_image = attr.new_image_borrowed(rgba_slice, width, height, 0.0)
-                        .map_err(|e| format!("IQ NewImage err: {:?}", e))?;
-                    
-                    let mut quant = attr.quantize(&mut iq_image)
-                        .map_err(|e| format!("IQ Quantize err: {:?}", e))?;
-                    
-                    quant.set_dithering_level(1.0).map_err(|e| format!("IQ Dither err: {:?}", e))?;
-                    
-                    let (palette, pixels): (Vec<imagequant::RGBA>, Vec<u8>) = quant.remapped(&mut iq_image)
-                        .map_err(|e| format!("IQ Remap err: {:?}", e))?;
-                    
-                    // 3. Encode with png crate
-                    let mut buffer = Vec::new();
-                    let mut cursor = std::io::Cursor::new(&mut buffer);
-                    {
-                        let mut encoder = png::Encoder::new(&mut cursor, width as u32, height as u32);
-                        encoder.set_color(png::ColorType::Indexed);
-                        encoder.set_depth(png::BitDepth::Eight);
-                        
-                        // Prepare PLTE (RGB) and tRNS (A)
-                        let mut plte: Vec<u8> = Vec::with_capacity(palette.len() * 3);
-                        let mut trns: Vec<u8> = Vec::with_capacity(palette.len());
-                        let mut has_transparency = false;
-                        
-                        for px in &palette {
-                            plte.push(px.r);
-                            plte.push(px.g);
-                            plte.push(px.b);
-                            trns.push(px.a);
-                            if px.a < 255 { has_transparency = true; }
-                        }
-                        
-                        encoder.set_palette(plte);
-                        if has_transparency {
-                            encoder.set_trns(trns);
-                        }
-                        
-                        let mut writer = encoder.write_header().map_err(|e| format!("PNG Header err: {}", e))?;
-                        writer.write_image_data(&pixels).map_err(|e| format!("PNG Write err: {}", e))?;
-                    }
-                    
-                    Ok(buffer)
-                })();
-
-                match quant_result {
-                    Ok(data) => {
-                         // 4. Final optimization with oxipng
-                         let mut oxi_options = oxipng::Options::from_preset(2);
-                         oxi_options.strip = oxipng::StripChunks::All; 
-                         match oxipng::optimize_from_memory(&data, &oxi_options) {
-                             Ok(final_data) => { compressed_data = final_data; Ok(()) },
-                             Err(e) => {
-                                 println!("[Compress] Oxipng failed on quant result: {}, using lodepng raw.", e);
-                                 compressed_data = data;
-                                 Ok(())
-                             }
-                         }
-                    },
-                    Err(e) => {
-                        println!("[Compress] Lossy failed: {}, falling back to lossless.", e);
-                        // Fallback to lossless logic if quantization fails (e.g. image too simple or opaque)
-                        let oxi_options = oxipng::Options::from_preset(2);
-                        match oxipng::optimize_from_memory(&input_data, &oxi_options) {
-                            Ok(data) => { compressed_data = data; Ok(()) },
-                            Err(e) => Err(format!("PNG optimization failed: {}", e))
-                        }
-                    }
-                }
-            } else {
-                // Lossless mode (existing logic)
-                let mut oxi_options = oxipng::Options::from_preset(2); 
-                oxi_options.strip = oxipng::StripChunks::Safe;
-                match oxipng::optimize_from_memory(&input_data, &oxi_options) {
-                    Ok(data) => {
-                        compressed_data = data;
-                        Ok(())
-                    },
-                    Err(e) => Err(format!("PNG optimization failed: {}", e))
-                }
-            }
-        },
-        "jpg" | "jpeg" => {
-            // JPEG Process using image crate
-             let img = match image::open(path) {
-                Ok(i) => i,
-                Err(e) => return create_error_result(&file_path_str, &format!("Failed to open image: {}", e)),
-            };
-            
-            let quality = if options.mode == "lossless" { 100 } else { options.quality };
-            println!("DEBUG: JPEG compression with quality={}", quality);
-
-            let mut writer = std::io::Cursor::new(Vec::new());
-            let mut encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut writer, quality);
-            
-            match encoder.encode_image(&img) {
-                Ok(_) => {
-                    compressed_data = writer.into_inner();
-                    Ok(())
-                },
-                Err(e) => Err(format!("JPEG encoding failed: {}", e))
-            }
-        },
-        _ => Err("Unsupported format".to_string())
-    };
+/// `depth` limits how far folders are walked, 1 only lists their direct children.
+/// Symlinks inside folders are skipped unless `follow_symlinks` is set.
+#[tauri::command]
+async fn scan_paths(paths: Vec<String>, depth: Option<usize>, follow_symlinks: Option<bool>) -> Vec<String> {
+    collect_image_paths(&paths, depth, follow_symlinks.unwrap_or(false))
+}
+
+/// `scan_paths` without images smaller than `min_width` x `min_height`, e.g. icons that are
+/// already optimal. Only headers are read, in parallel. Files whose size can't be read are
+/// kept, like `scan_paths_with_options` does.
+#[tauri::command]
+async fn scan_paths_by_dimension(paths: Vec<String>, min_width: Option<u32>, min_height: Option<u32>) -> Vec<String> {
+    let options = ScanOptions { min_width, min_height, ..Default::default() };
+    collect_image_paths(&paths, None, false)
+        .into_par_iter()
+        .filter(|path| {
+            read_dimensions(Path::new(path)).map_or(true, |(width, height)| options.dimensions_match(width, height))
+        })
+        .collect()
+}
 
-    if let Err(e) = compression_result {
-         return create_error_result(&file_path_str, &e);
+#[tauri::command]
+async fn scan_paths_with_options(paths: Vec<String>, options: ScanOptions) -> ScanResult {
+    let mut result = ScanResult::default();
+    for path in collect_image_paths(&paths, None, options.follow_symlinks) {
+        match scan_file(path, &options) {
+            Some(file) => result.files.push(file),
+            None => result.excluded_by_dimension += 1,
+        }
     }
+    result
+}
 
-    let compressed_size = compressed_data.len() as u64;
+/// Like `scan_paths`, leaving out every file matching one of `exclude_globs`,
+/// e.g. "**/node_modules/**" drops that whole subtree.
+#[tauri::command]
+async fn scan_paths_filtered(paths: Vec<String>, exclude_globs: Vec<String>) -> Result<Vec<String>, CompressionError> {
+    let mut patterns = Vec::new();
+    let mut errors = Vec::new();
+    for glob in &exclude_globs {
+        match glob::Pattern::new(glob) {
+            Ok(pattern) => patterns.push(pattern),
+            Err(e) => errors.push(format!("invalid exclude pattern '{}': {}", glob, e)),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(CompressionError::Validation(errors));
+    }
 
-    println!("[Compress] {} - Original: {} -> Compressed: {}", file_path_str, original_size, compressed_size);
+    // Match the way the platform compares file names
+    let match_options = glob::MatchOptions {
+        case_sensitive: !cfg!(any(windows, target_os = "macos")),
+        ..Default::default()
+    };
+    Ok(collect_image_paths(&paths, None, false)
+        .into_iter()
+        .filter(|path| !patterns.iter().any(|p| p.matches_with(path, match_options)))
+        .collect())
+}
 
-    // Save Logic
-    // We strictly save if size is smaller. 
-    // If size is larger, we keep original (effectively skipping), unless user explicitly wanted re-encode (but usually size increase is bad).
-    if compressed_size < original_size {
-        if let Err(e) = fs::write(path, &compressed_data) {
-             return create_error_result(&file_path_str, &format!("Failed to save: {}", e));
-        }
-        
-        CompressionResult {
-            file_path: file_path_str,
-            original_size: original_size,
-            compressed_size: compressed_size,
-            saved_before: original_size - compressed_size,
-            status: "success".to_string(),
-            error: None,
+/// Like `scan_paths`, honoring `.gitignore`, `.ignore` and `.pngignore` files found along the
+/// way (nested ones included, even outside a git repository). Hidden entries, `.git` among
+/// them, and `node_modules` folders are always skipped.
+#[tauri::command]
+async fn scan_paths_respectful(paths: Vec<String>) -> Vec<String> {
+    let mut results = Vec::new();
+    for p in &paths {
+        let path = Path::new(p);
+        if path.is_file() {
+            if has_supported_extension(path) {
+                results.push(path.to_string_lossy().to_string());
+            }
+            continue;
         }
-    } else {
-        // Did not save because it got bigger or same
-        CompressionResult {
-             file_path: file_path_str,
-            original_size: original_size,
-            compressed_size: original_size,
-            saved_before: 0,
-            status: "success".to_string(),
-            error: None,
+        let walker = ignore::WalkBuilder::new(path)
+            .add_custom_ignore_filename(".pngignore")
+            .require_git(false)
+            .filter_entry(|entry| entry.file_name() != "node_modules")
+            .build();
+        for entry in walker.filter_map(|e| e.ok()) {
+            if entry.file_type().is_some_and(|t| t.is_file()) && has_supported_extension(entry.path()) {
+                results.push(entry.path().to_string_lossy().to_string());
+            }
         }
     }
+    results
 }
 
-fn create_error_result(path: &str, error: &str) -> CompressionResult {
-    CompressionResult {
-        file_path: path.to_string(),
-        original_size: 0,
-        compressed_size: 0,
-        saved_before: 0,
-        status: "error".to_string(),
-        error: Some(error.to_string()),
-    }
+/// Like `scan_paths`, with the size, modification time and dimensions of each file.
+#[tauri::command]
+async fn scan_paths_with_meta(paths: Vec<String>, depth: Option<usize>) -> Vec<ScannedFile> {
+    let options = ScanOptions::default();
+    collect_image_paths(&paths, depth, false)
+        .into_iter()
+        .filter_map(|path| scan_file(path, &options))
+        .collect()
 }
 
-fn main() {
+/// Scan like `scan_paths_with_options`, but emit a "scan-file-found" event per file as soon as
+/// it is found. Returns the number of files emitted, the frontend builds the list itself.
+#[tauri::command]
+async fn scan_paths_streaming(
+    app: tauri::AppHandle,
+    paths: Vec<String>,
+    options: ScanOptions,
+) -> Result<usize, CompressionError> {
+    stream_scanned_files(paths, options, |file| {
+        let _ = app.emit("scan-file-found", &file);
+    })
+    .await
+}
+
+/// Walk `paths` on a blocking thread and hand every scanned file to `found` in walk order,
+/// returning how many there were.
+async fn stream_scanned_files(
+    paths: Vec<String>,
+    options: ScanOptions,
+    mut found: impl FnMut(ScannedFile),
+) -> Result<usize, CompressionError> {
+    // Bounded, so a consumer that can't keep up pauses the walk instead of losing files
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<ScannedFile>(options.scan_buffer_size.max(1));
+    let walker = tauri::async_runtime::spawn_blocking(move || {
+        walk_image_paths(&paths, None, options.follow_symlinks, |path| {
+            if let Some(file) = scan_file(path, &options) {
+                // Only fails once the receiver is gone, there is no one left to tell then
+                let _ = tx.blocking_send(file);
+            }
+        });
+    });
+
+    let mut count = 0;
+    while let Some(file) = rx.recv().await {
+        found(file);
+        count += 1;
+    }
+    walker
+        .await
+        .map_err(|e| CompressionError::Io(std::io::Error::other(e.to_string())))?;
+    Ok(count)
+}
+
+/// Measure a scanned file, `None` when it is excluded by the dimension filters.
+fn scan_file(path: String, options: &ScanOptions) -> Option<ScannedFile> {
+    let metadata = fs::metadata(&path).ok();
+    let size = metadata.as_ref().map_or(0, |m| m.len());
+    let modified = metadata
+        .and_then(|m| m.modified().ok())
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs());
+    match read_dimensions(Path::new(&path)) {
+        Ok((width, height)) => options.dimensions_match(width, height).then_some(ScannedFile {
+            path,
+            size,
+            modified,
+            width: Some(width),
+            height: Some(height),
+            warning: None,
+        }),
+        // Keep files we can't measure, compression will report the actual problem
+        Err(e) => Some(ScannedFile {
+            path,
+            size,
+            modified,
+            width: None,
+            height: None,
+            warning: Some(format!("Failed to read dimensions: {}", e)),
+        }),
+    }
+}
+
+/// Each input path is walked on the rayon pool. Paths are returned canonicalized, and a file
+/// reached twice (a path passed twice, a file inside a folder also passed, or a link to a file
+/// already found) is only listed the first time. Callers shouldn't rely on the order, it
+/// currently follows `paths`.
+fn collect_image_paths(paths: &[String], depth: Option<usize>, follow_symlinks: bool) -> Vec<String> {
+    let per_path: Vec<Vec<String>> = paths
+        .par_iter()
+        .map(|p| {
+            let mut found = Vec::new();
+            walk_image_paths(std::slice::from_ref(p), depth, follow_symlinks, |path| {
+                found.push(canonical_path(path))
+            });
+            found
+        })
+        .collect();
+    let mut seen = HashSet::new();
+    per_path
+        .into_iter()
+        .flatten()
+        .filter(|path| seen.insert(path.clone()))
+        .collect()
+}
+
+/// `path` with symlinks and relative parts resolved, as is when that fails. Windows drive paths
+/// lose the `\\?\` prefix `fs::canonicalize` adds, the frontend shows these paths.
+fn canonical_path(path: String) -> String {
+    match fs::canonicalize(&path) {
+        Ok(canonical) => {
+            let canonical = canonical.to_string_lossy().to_string();
+            if cfg!(windows) && canonical.starts_with(r"\\?\") && !canonical.starts_with(r"\\?\UNC\") {
+                canonical[4..].to_string()
+            } else {
+                canonical
+            }
+        }
+        Err(_) => path,
+    }
+}
+
+/// Call `found` with every supported image in `paths`, walking directories recursively
+/// down to `depth` levels (unlimited when `None`). Symlinks found in directories are skipped
+/// unless `follow_symlinks` is set, the walk then stays on the file system it started on
+/// and walkdir reports link loops as errors, which are skipped like unreadable entries.
+fn walk_image_paths(paths: &[String], depth: Option<usize>, follow_symlinks: bool, mut found: impl FnMut(String)) {
+    for p in paths {
+        let path = Path::new(p);
+        if path.is_dir() {
+            let walker = WalkDir::new(path)
+                .follow_links(follow_symlinks)
+                .same_file_system(follow_symlinks)
+                .max_depth(depth.unwrap_or(usize::MAX));
+            for entry in walker.into_iter().filter_map(|e| e.ok()) {
+                if entry.file_type().is_file() && has_supported_extension(entry.path()) {
+                    found(entry.path().to_string_lossy().to_string());
+                }
+            }
+        } else if path.is_file() && has_supported_extension(path) {
+            found(path.to_string_lossy().to_string());
+        }
+    }
+}
+
+fn has_supported_extension(path: &Path) -> bool {
+    let ext = path
+        .extension()
+        .and_then(|s| s.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+    SUPPORTED_EXTENSIONS.contains(&ext.as_str())
+}
+
+/// Build options from the keys that differ from the defaults, returning the warnings for
+/// unknown keys alongside them.
+#[tauri::command]
+async fn build_options(
+    values: HashMap<String, serde_json::Value>,
+) -> Result<ConvertedOptions, Vec<ConversionError>> {
+    ConvertedOptions::try_from(values)
+}
+
+/// Per-project settings file read by `load_config`.
+const CONFIG_FILE_NAME: &str = ".pngcompress.toml";
+
+/// Read `.pngcompress.toml` from `project_dir`, keys are the `CompressionOptions` field names.
+/// Missing keys, or a missing file, fall back to the defaults (see `CompressionOptions::base`).
+#[tauri::command]
+async fn load_config(project_dir: String) -> Result<CompressionOptions, CompressionError> {
+    let path = Path::new(&project_dir).join(CONFIG_FILE_NAME);
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(CompressionOptions::base()),
+        Err(e) => return Err(e.into()),
+    };
+    let options: CompressionOptions = toml::from_str(&text)
+        .map_err(|e| CompressionError::Config(format!("{}: {}", path.display(), e)))?;
+    options.validate()?;
+    Ok(options)
+}
+
+/// Names the preset `main` loads as the base for every `CompressionOptions` it receives.
+const PROFILE_ENV: &str = "PNGCOMPRESS_PROFILE";
+/// Overrides where presets are looked up, see `presets_dir`.
+const PRESETS_DIR_ENV: &str = "PNGCOMPRESS_PRESETS_DIR";
+/// `identifier` in tauri.conf.json, which names the app config directory.
+const APP_IDENTIFIER: &str = "com.pngcompress.app";
+
+/// The `PNGCOMPRESS_PROFILE` preset, loaded once at startup.
+static BASE_OPTIONS: std::sync::OnceLock<CompressionOptions> = std::sync::OnceLock::new();
+
+impl CompressionOptions {
+    /// What keys missing from the frontend's options, a config file or a preset fall back to:
+    /// the `PNGCOMPRESS_PROFILE` preset when one was loaded, the defaults otherwise.
+    fn base() -> Self {
+        BASE_OPTIONS.get().cloned().unwrap_or_default()
+    }
+
+    /// The preset named by `PNGCOMPRESS_PROFILE`, the defaults when it isn't set.
+    fn from_env() -> Result<Self, CompressionError> {
+        match std::env::var(PROFILE_ENV) {
+            Ok(name) if !name.is_empty() => load_preset(&name),
+            _ => Ok(CompressionOptions::default()),
+        }
+    }
+}
+
+/// `PNGCOMPRESS_PRESETS_DIR`, or `presets` in the app config directory. That is where Tauri's
+/// `app_config_dir` points, worked out here because presets load before the app runs.
+fn presets_dir() -> Option<PathBuf> {
+    if let Some(dir) = std::env::var_os(PRESETS_DIR_ENV) {
+        return Some(PathBuf::from(dir));
+    }
+    let home = || std::env::var_os("HOME").map(PathBuf::from);
+    let config = if cfg!(windows) {
+        std::env::var_os("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        home().map(|h| h.join("Library").join("Application Support"))
+    } else {
+        std::env::var_os("XDG_CONFIG_HOME").map(PathBuf::from).or_else(|| home().map(|h| h.join(".config")))
+    };
+    Some(config?.join(APP_IDENTIFIER).join("presets"))
+}
+
+/// Read `<name>.preset.json` from `presets_dir`, keys are the `CompressionOptions` field names
+/// and missing keys keep their defaults.
+fn load_preset(name: &str) -> Result<CompressionOptions, CompressionError> {
+    // The name ends up in a path, keep it from escaping the presets directory
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+        return Err(CompressionError::Validation(vec![format!("invalid preset name '{}'", name)]));
+    }
+    let dir = presets_dir().ok_or_else(|| CompressionError::Config("no presets directory".to_string()))?;
+    let path = dir.join(format!("{}.preset.json", name));
+    let text = fs::read_to_string(&path).map_err(|e| CompressionError::Config(format!("{}: {}", path.display(), e)))?;
+    let options: CompressionOptions = serde_json::from_str(&text)
+        .map_err(|e| CompressionError::Config(format!("{}: {}", path.display(), e)))?;
+    options.validate()?;
+    Ok(options)
+}
+
+/// Run the same checks `compress_files` does up front, so a form can report every bad
+/// value before starting a batch.
+#[tauri::command]
+async fn validate_options(options: CompressionOptions) -> Result<(), Vec<String>> {
+    options.validate().map_err(|e| match e {
+        CompressionError::Validation(errors) => errors,
+        other => vec![other.to_string()],
+    })
+}
+
+/// Write `options` to `.pngcompress.toml` in `dir` for `load_config`. The file is written
+/// next to its final name and renamed over it, so a crash never leaves half a config.
+#[tauri::command]
+async fn save_config(dir: String, options: CompressionOptions) -> Result<(), CompressionError> {
+    let dir = Path::new(&dir);
+    if !dir.is_dir() {
+        return Err(CompressionError::Io(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("Directory not found: {}", dir.display()),
+        )));
+    }
+    options.validate()?;
+    let text = toml::to_string_pretty(&options).map_err(|e| CompressionError::Config(e.to_string()))?;
+    let temp = dir.join(format!("{}.tmp", CONFIG_FILE_NAME));
+    fs::write(&temp, text)?;
+    fs::rename(&temp, dir.join(CONFIG_FILE_NAME))?;
+    Ok(())
+}
+
+#[tauri::command]
+async fn analyze_color_count(path: String, max_count: usize) -> Result<u32, CompressionError> {
+    let img = open_image(Path::new(&path))?;
+    Ok(estimate_unique_colors(&img.to_rgba8(), max_count) as u32)
+}
+
+/// List the chunks of a PNG in file order, to help pick a `strip_mode`. Only the chunk
+/// headers are read, the image data is never inflated.
+#[tauri::command]
+async fn inspect_png_chunks(path: String) -> Result<Vec<ChunkInfo>, CompressionError> {
+    let data = fs::read(&path)?;
+    match image::guess_format(&data) {
+        Ok(image::ImageFormat::Png) => {}
+        Ok(format) => return Err(CompressionError::UnsupportedFormat(format_name(format).to_string())),
+        Err(_) => return Err(CompressionError::UnsupportedFormat(path)),
+    }
+    Ok(png_chunks(&data)
+        .into_iter()
+        .map(|(name, chunk_data)| ChunkInfo {
+            name: String::from_utf8_lossy(&name).to_string(),
+            size: chunk_data.len() as u32,
+        })
+        .collect())
+}
+
+#[tauri::command]
+async fn analyze_image(path: String) -> Result<ImageAnalysis, CompressionError> {
+    let data = fs::read(&path)?;
+    let format = image::guess_format(&data).map_err(|_| CompressionError::UnsupportedFormat(path.clone()))?;
+    let (width, height) = image::io::Reader::with_format(std::io::Cursor::new(&data), format)
+        .into_dimensions()
+        .map_err(|e| CompressionError::Decode(e.to_string()))?;
+
+    let icc_profile = read_icc_profile(&data, format);
+    Ok(ImageAnalysis {
+        format: format_name(format).to_string(),
+        width,
+        height,
+        has_icc_profile: icc_profile.is_some(),
+        icc_profile_name: icc_profile.as_deref().and_then(icc_profile_description),
+        icc_profile_size_bytes: icc_profile.map_or(0, |p| p.len() as u32),
+    })
+}
+
+/// Describe the oxipng settings behind a `png_effort` preset, without touching any file.
+#[tauri::command]
+async fn preview_oxipng_options(preset: u8) -> Result<OxipngOptionsInfo, CompressionError> {
+    // The range `png_effort` accepts, so the preview never describes a preset compressing refuses
+    if !(1..=6).contains(&preset) {
+        return Err(CompressionError::Validation(vec![format!("preset {} out of range 1-6", preset)]));
+    }
+    let options = oxipng::Options::from_preset(preset);
+    let chunk_names = |chunks: &oxipng::IndexSet<[u8; 4]>| {
+        chunks.iter().map(|c| String::from_utf8_lossy(c).into_owned()).collect::<Vec<_>>().join(", ")
+    };
+    Ok(OxipngOptionsInfo {
+        preset,
+        filters: options.filter.iter().map(|f| f.to_string()).collect(),
+        deflate: match options.deflate {
+            oxipng::Deflaters::Libdeflater { compression } => format!("libdeflater, level {}", compression),
+            oxipng::Deflaters::Zopfli { iterations } => format!("zopfli, {} iterations", iterations),
+        },
+        interlace: match options.interlace {
+            Some(interlacing) => interlacing.to_string(),
+            None => "unchanged".to_string(),
+        },
+        strip: match &options.strip {
+            oxipng::StripChunks::None => "none".to_string(),
+            oxipng::StripChunks::Safe => "safe".to_string(),
+            oxipng::StripChunks::All => "all".to_string(),
+            oxipng::StripChunks::Strip(chunks) => format!("strip {}", chunk_names(chunks)),
+            oxipng::StripChunks::Keep(chunks) => format!("all except {}", chunk_names(chunks)),
+        },
+    })
+}
+
+/// Read an image's dimensions and pixel layout from its header, without decoding it.
+#[tauri::command]
+async fn get_file_info(path: String) -> Result<FileInfo, CompressionError> {
+    let data = fs::read(&path)?;
+    let format = image::guess_format(&data).map_err(|_| CompressionError::UnsupportedFormat(path.clone()))?;
+    let (width, height) = image::io::Reader::with_format(std::io::Cursor::new(&data), format)
+        .into_dimensions()
+        .map_err(|e| CompressionError::Decode(e.to_string()))?;
+    let (color_type, bit_depth, has_alpha) = header_color_type(&data, format)?;
+    Ok(FileInfo {
+        file_size: data.len() as u64,
+        path,
+        width,
+        height,
+        color_type: color_type.to_string(),
+        bit_depth,
+        has_alpha,
+        format: format_name(format).to_string(),
+    })
+}
+
+/// Color type name, bits per channel and whether there is transparency, from the header.
+/// PNGs with a tRNS chunk count as transparent.
+fn header_color_type(data: &[u8], format: image::ImageFormat) -> Result<(&'static str, u8, bool), CompressionError> {
+    use image::ImageDecoder;
+    if format == image::ImageFormat::Png {
+        let reader = png::Decoder::new(data).read_info().map_err(|e| CompressionError::Decode(e.to_string()))?;
+        let info = reader.info();
+        let name = match info.color_type {
+            png::ColorType::Grayscale => "gray",
+            png::ColorType::GrayscaleAlpha => "gray_alpha",
+            png::ColorType::Rgb => "rgb",
+            png::ColorType::Rgba => "rgba",
+            png::ColorType::Indexed => "indexed",
+        };
+        let has_alpha = info.color_type.samples() % 2 == 0 || info.trns.is_some();
+        return Ok((name, info.bit_depth as u8, has_alpha));
+    }
+    let cursor = std::io::Cursor::new(data);
+    let decode_err = |e: image::ImageError| CompressionError::Decode(e.to_string());
+    let color = match format {
+        image::ImageFormat::Jpeg => image::codecs::jpeg::JpegDecoder::new(cursor).map_err(decode_err)?.color_type(),
+        image::ImageFormat::Gif => image::codecs::gif::GifDecoder::new(cursor).map_err(decode_err)?.color_type(),
+        image::ImageFormat::Tiff => image::codecs::tiff::TiffDecoder::new(cursor).map_err(decode_err)?.color_type(),
+        image::ImageFormat::Bmp => image::codecs::bmp::BmpDecoder::new(cursor).map_err(decode_err)?.color_type(),
+        image::ImageFormat::WebP => image::codecs::webp::WebPDecoder::new(cursor).map_err(decode_err)?.color_type(),
+        _ => return Err(CompressionError::UnsupportedFormat(format_name(format).to_string())),
+    };
+    let name = match color.channel_count() {
+        1 => "gray",
+        2 => "gray_alpha",
+        3 => "rgb",
+        _ => "rgba",
+    };
+    let bit_depth = (color.bits_per_pixel() / color.channel_count() as u16) as u8;
+    Ok((name, bit_depth, color.has_alpha()))
+}
+
+#[tauri::command]
+async fn compress_files(
+    app: tauri::AppHandle,
+    cancel: tauri::State<'_, CancellationToken>,
+    paths: Vec<String>,
+    options: CompressionOptions,
+) -> Result<CompressionSummary, CompressionError> {
+    options.validate()?;
+    let mut paths = paths;
+    if let Some(order @ ("size_desc" | "size_asc")) = options.processing_order.as_deref() {
+        // Missing files sort as empty, they fail fast anyway
+        let size = |p: &String| fs::metadata(p).map_or(0, |m| m.len());
+        if order == "size_desc" {
+            paths.sort_by_cached_key(|p| std::cmp::Reverse(size(p)));
+        } else {
+            paths.sort_by_cached_key(size);
+        }
+    }
+    let cancelled = cancel.0.clone();
+    cancelled.store(false, Ordering::SeqCst);
+    let total = paths.len();
+    let _ = app.emit("compression-started", CompressionStartedEvent { total, paths: paths.clone() });
+    let progress = Arc::new(Mutex::new(BatchProgress::new()));
+    let throttle = ProgressThrottle::new(options.progress_throttle_ms);
+    // Done and total files per parent directory
+    let mut directories: HashMap<String, (usize, usize)> = HashMap::new();
+    for file_path in &paths {
+        directories.entry(parent_directory(file_path)).or_default().1 += 1;
+    }
+    let directories = Arc::new(Mutex::new(directories));
+    let benchmark_mode = options.benchmark_mode;
+    let concurrency = options.concurrency;
+    let log_path = options.log_path.clone();
+    let batch_size = options.batch_size;
+    let abort_on_error = options.abort_on_error;
+    let first_error: Mutex<Option<String>> = Mutex::new(None);
+    let started = std::time::Instant::now();
+    let mut options = options;
+    prepare_mirrored_output(&paths, &mut options)?;
+    let processor = FileProcessor::new(options);
+
+    let process = |file_path: &String| {
+        let path = Path::new(file_path);
+        // Files left after a cancel still get a result, so the frontend sees the batch finish
+        let result = if cancelled.load(Ordering::SeqCst) {
+            create_skipped_result(file_path, "cancelled", 0)
+        } else {
+            processor.process(path)
+        };
+        if abort_on_error && result.status == "error" {
+            first_error.lock().unwrap().get_or_insert_with(|| file_path.clone());
+            cancelled.store(true, Ordering::SeqCst);
+        }
+        emit_progress(&app, &progress, total, &result, &throttle);
+
+        let directory = parent_directory(file_path);
+        let mut directories_lock = directories.lock().unwrap();
+        if let Some((dir_done, dir_total)) = directories_lock.get_mut(&directory) {
+            *dir_done += 1;
+            if dir_done == dir_total {
+                let _ = app.emit(
+                    "compression-directory-complete",
+                    DirectoryProgressEvent { directory, done: *dir_done, total: *dir_total },
+                );
+            }
+        }
+        result
+    };
+
+    // Process files in parallel using rayon, one chunk at a time when `batch_size` is set
+    let chunk_size = batch_size.unwrap_or(total).max(1);
+    let chunk_count = total.div_ceil(chunk_size);
+    let results: Vec<CompressionResult> = run_in_pool(concurrency, || {
+        let mut results = Vec::with_capacity(total);
+        for (index, chunk) in paths.chunks(chunk_size).enumerate() {
+            results.par_extend(chunk.par_iter().map(process));
+            if batch_size.is_some() {
+                let _ = app.emit(
+                    "compression-batch-complete",
+                    BatchChunkEvent { index, chunk_count, done: results.len(), total },
+                );
+            }
+        }
+        results
+    });
+
+    if benchmark_mode != BenchmarkMode::Off {
+        let report = BenchmarkReport::from_results(benchmark_mode, &results, started.elapsed());
+        let _ = app.emit("benchmark-result", report);
+    }
+
+    let mut summary = CompressionSummary::from_results(&results);
+    summary.first_error_path = first_error.into_inner().unwrap();
+    summary.aborted_due_to_error = summary.first_error_path.is_some();
+    let _ = app.emit("compression-complete", summary.clone());
+    // The files are written either way, the frontend hears about that first
+    if let Some(log_path) = log_path {
+        append_session_log(Path::new(&log_path), &results)?;
+    }
+    Ok(summary)
+}
+
+/// `compress_files` for callers that can't listen to events: nothing is emitted and the
+/// results come back in the order of `paths` once the whole batch is done.
+#[tauri::command]
+async fn compress_files_blocking(
+    cancel: tauri::State<'_, CancellationToken>,
+    paths: Vec<String>,
+    options: CompressionOptions,
+) -> Result<Vec<CompressionResult>, CompressionError> {
+    options.validate()?;
+    let cancelled = cancel.0.clone();
+    cancelled.store(false, Ordering::SeqCst);
+    let concurrency = options.concurrency;
+    let log_path = options.log_path.clone();
+    let mut options = options;
+    prepare_mirrored_output(&paths, &mut options)?;
+    let processor = FileProcessor::new(options);
+
+    let results: Vec<CompressionResult> = run_in_pool(concurrency, || paths.par_iter().map(|file_path| {
+        if cancelled.load(Ordering::SeqCst) {
+            create_skipped_result(file_path, "cancelled", 0)
+        } else {
+            processor.process(Path::new(file_path))
+        }
+    }).collect());
+
+    if let Some(log_path) = log_path {
+        append_session_log(Path::new(&log_path), &results)?;
+    }
+    Ok(results)
+}
+
+/// Append one JSON line with the batch's results, earlier sessions in the file are kept.
+fn append_session_log(path: &Path, results: &[CompressionResult]) -> Result<(), CompressionError> {
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let entry = serde_json::json!({ "timestamp": timestamp, "results": results });
+    use std::io::Write;
+    let mut file = fs::OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", entry)?;
+    Ok(())
+}
+
+/// One file of a `compress_files_mapped` batch with its own options.
+#[derive(Deserialize, Debug, Clone)]
+struct CompressItem {
+    path: String,
+    options: CompressionOptions,
+}
+
+/// `compress_files` with options per file, e.g. lossy for screenshots and lossless for photos.
+/// Emits the same events, except "benchmark-result" since benchmark modes can differ per file.
+#[tauri::command]
+async fn compress_files_mapped(
+    app: tauri::AppHandle,
+    cancel: tauri::State<'_, CancellationToken>,
+    items: Vec<CompressItem>,
+) -> Result<CompressionSummary, CompressionError> {
+    let mut errors = Vec::new();
+    for item in &items {
+        if let Err(CompressionError::Validation(item_errors)) = item.options.validate() {
+            errors.extend(item_errors.into_iter().map(|e| format!("{}: {}", item.path, e)));
+        }
+    }
+    if !errors.is_empty() {
+        return Err(CompressionError::Validation(errors));
+    }
+    let cancelled = cancel.0.clone();
+    cancelled.store(false, Ordering::SeqCst);
+    let total = items.len();
+    let progress = Arc::new(Mutex::new(BatchProgress::new()));
+    let throttle = ProgressThrottle::new(None);
+
+    let results: Vec<CompressionResult> = items.par_iter().map(|item| {
+        let result = if cancelled.load(Ordering::SeqCst) {
+            create_skipped_result(&item.path, "cancelled", 0)
+        } else {
+            process_single_file(Path::new(&item.path), &item.options)
+        };
+        emit_progress(&app, &progress, total, &result, &throttle);
+        result
+    }).collect();
+
+    let summary = CompressionSummary::from_results(&results);
+    let _ = app.emit("compression-complete", summary.clone());
+    Ok(summary)
+}
+
+fn parent_directory(file_path: &str) -> String {
+    Path::new(file_path)
+        .parent()
+        .map(|p| p.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// Files finished so far in a batch, shared by the workers for "compression-progress".
+struct BatchProgress {
+    done: usize,
+    /// `original_size` summed over the files that weren't skipped
+    bytes_processed: u64,
+    started: std::time::Instant,
+}
+
+impl BatchProgress {
+    fn new() -> Self {
+        BatchProgress { done: 0, bytes_processed: 0, started: std::time::Instant::now() }
+    }
+
+    /// Count a finished file, returning the new `done` and the throughput so far.
+    fn record(&mut self, result: &CompressionResult) -> (usize, f64) {
+        self.done += 1;
+        if !result.status.starts_with("skipped") {
+            self.bytes_processed += result.original_size;
+        }
+        (self.done, bytes_per_second(self.bytes_processed, self.started.elapsed()))
+    }
+}
+
+fn bytes_per_second(bytes: u64, elapsed: std::time::Duration) -> f64 {
+    let seconds = elapsed.as_secs_f64();
+    if seconds > 0.0 {
+        bytes as f64 / seconds
+    } else {
+        0.0
+    }
+}
+
+/// Rate limit for "compression-progress" events, see `progress_throttle_ms`.
+struct ProgressThrottle {
+    interval: Option<std::time::Duration>,
+    last_emit: Mutex<Option<std::time::Instant>>,
+}
+
+impl ProgressThrottle {
+    fn new(interval_ms: Option<u64>) -> Self {
+        ProgressThrottle {
+            interval: interval_ms.map(std::time::Duration::from_millis),
+            last_emit: Mutex::new(None),
+        }
+    }
+
+    /// Whether an event can go out now, recording it as sent if so.
+    fn allow(&self, done: usize, total: usize) -> bool {
+        let Some(interval) = self.interval else {
+            return true;
+        };
+        let mut last_emit = self.last_emit.lock().unwrap();
+        let now = std::time::Instant::now();
+        let due = last_emit.is_none_or(|last| now.duration_since(last) >= interval);
+        if due || done == total {
+            *last_emit = Some(now);
+            return true;
+        }
+        false
+    }
+}
+
+/// Count a finished file and send the "compression-progress" event for it, unless `throttle`
+/// holds it back.
+fn emit_progress(
+    app: &tauri::AppHandle,
+    progress: &Mutex<BatchProgress>,
+    total: usize,
+    result: &CompressionResult,
+    throttle: &ProgressThrottle,
+) {
+    let mut progress_lock = progress.lock().unwrap();
+    let (current_done, bytes_per_second) = progress_lock.record(result);
+    if !throttle.allow(current_done, total) {
+        return;
+    }
+
+    // Emit progress event
+    // Note: Generic error handling here because Emitter can fail if window is closed
+    let _ = app.emit(
+        "compression-progress",
+        ProgressEvent {
+            done: current_done,
+            total,
+            result: result.clone(),
+            bytes_per_second,
+        },
+    );
+}
+
+/// Put the `<path>.orig` copy made by the `backup` option back in place of `path`.
+#[tauri::command]
+async fn restore_backup(path: String) -> Result<(), CompressionError> {
+    let backup = backup_file_path(Path::new(&path));
+    if !backup.is_file() {
+        return Err(CompressionError::Io(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("No backup found at {}", backup.display()),
+        )));
+    }
+    // rename replaces the compressed file and removes the backup in one step
+    fs::rename(&backup, &path)?;
+    Ok(())
+}
+
+/// Outcome of restoring one backup in `restore_backups_in_dir`, `status` is "restored" or "error".
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct RestoreResult {
+    original_path: String,
+    status: String,
+    error: Option<String>,
+}
+
+/// Undo a session: put every `<name>.orig` backup in `dir` back in place of `<name>`.
+/// Only `dir` itself is searched unless `recursive` is set.
+#[tauri::command]
+async fn restore_backups_in_dir(dir: String, recursive: Option<bool>) -> Result<Vec<RestoreResult>, CompressionError> {
+    if !Path::new(&dir).is_dir() {
+        return Err(CompressionError::Io(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("Directory not found: {}", dir),
+        )));
+    }
+    tauri::async_runtime::spawn_blocking(move || {
+        let walker = if recursive.unwrap_or(false) {
+            WalkDir::new(&dir)
+        } else {
+            WalkDir::new(&dir).max_depth(1)
+        };
+        let backups: Vec<PathBuf> = walker
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file() && e.path().extension().is_some_and(|ext| ext == "orig"))
+            .map(|e| e.into_path())
+            .collect();
+        backups
+            .par_iter()
+            .map(|backup| {
+                // backup_file_path only appends ".orig", so dropping the extension gives the original
+                let original = backup.with_extension("");
+                let restored = fs::rename(backup, &original);
+                RestoreResult {
+                    original_path: original.to_string_lossy().to_string(),
+                    status: if restored.is_ok() { "restored" } else { "error" }.to_string(),
+                    error: restored.err().map(|e| e.to_string()),
+                }
+            })
+            .collect()
+    })
+    .await
+    .map_err(|e| CompressionError::Io(std::io::Error::other(e.to_string())))
+}
+
+/// Compress supported images created or modified in `dir` (not its subfolders), sending each
+/// result as an "auto-compression-result" event. Watching a directory again replaces its options.
+#[tauri::command]
+async fn watch_directory(
+    app: tauri::AppHandle,
+    watchers: tauri::State<'_, DirectoryWatchers>,
+    dir: String,
+    options: CompressionOptions,
+) -> Result<(), CompressionError> {
+    options.validate()?;
+    if !Path::new(&dir).is_dir() {
+        return Err(CompressionError::Io(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("Directory not found: {}", dir),
+        )));
+    }
+    let watch_error = |e: notify::Error| CompressionError::Io(std::io::Error::other(e.to_string()));
+    let (sender, events) = std::sync::mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender).map_err(watch_error)?;
+    notify::Watcher::watch(&mut watcher, Path::new(&dir), notify::RecursiveMode::NonRecursive).map_err(watch_error)?;
+    std::thread::spawn(move || auto_compress(app, events, FileProcessor::new(options)));
+    watchers.0.lock().unwrap().insert(dir, watcher);
+    Ok(())
+}
+
+#[tauri::command]
+async fn unwatch_directory(watchers: tauri::State<'_, DirectoryWatchers>, dir: String) -> Result<(), CompressionError> {
+    match watchers.0.lock().unwrap().remove(&dir) {
+        Some(_) => Ok(()),
+        None => Err(CompressionError::Io(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("{} is not being watched", dir),
+        ))),
+    }
+}
+
+/// Watcher thread: collect changed paths until `WATCH_DEBOUNCE` passes without events, then
+/// compress them. Runs until the watcher, and with it the sending side, is dropped.
+fn auto_compress(
+    app: tauri::AppHandle,
+    events: std::sync::mpsc::Receiver<notify::Result<notify::Event>>,
+    processor: FileProcessor,
+) {
+    // Modification time of every file this thread wrote, so its own writes don't trigger a rerun
+    let mut written: HashMap<PathBuf, std::time::SystemTime> = HashMap::new();
+    let mut pending: HashSet<PathBuf> = HashSet::new();
+    let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+    loop {
+        match events.recv_timeout(WATCH_DEBOUNCE) {
+            Ok(Ok(event)) => {
+                if matches!(event.kind, notify::EventKind::Create(_) | notify::EventKind::Modify(_)) {
+                    pending.extend(event.paths.into_iter().filter(|p| has_supported_extension(p)));
+                }
+            }
+            Ok(Err(e)) => println!("[Watch] {}", e),
+            Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {
+                for path in pending.drain() {
+                    let Some(before) = modified(&path) else {
+                        continue;
+                    };
+                    if written.get(&path) == Some(&before) {
+                        continue;
+                    }
+                    let result = processor.process(&path);
+                    // Conversions write a new file, which would be picked up as well
+                    for output in [path.clone(), PathBuf::from(&result.file_path)] {
+                        if let Some(after) = modified(&output) {
+                            written.insert(output, after);
+                        }
+                    }
+                    let _ = app.emit("auto-compression-result", result);
+                }
+            }
+            Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => break,
+        }
+    }
+}
+
+/// Stop the running `compress_files` batch, files already being compressed still finish.
+#[tauri::command]
+async fn cancel_compression(cancel: tauri::State<'_, CancellationToken>) -> Result<(), CompressionError> {
+    cancel.0.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+#[tauri::command]
+async fn compute_batch_savings_estimate(
+    paths: Vec<String>,
+    options: CompressionOptions,
+) -> Result<SavingsEstimate, CompressionError> {
+    options.validate()?;
+    let (total_bytes, ratios) = sample_savings_ratios(&paths, &options, 20)?;
+
+    let n = ratios.len() as f64;
+    let mean = ratios.iter().sum::<f64>() / n;
+    let variance = ratios.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / (n - 1.0).max(1.0);
+    let confidence = 1.96 * variance.sqrt() / n.sqrt();
+
+    Ok(SavingsEstimate {
+        estimated_total_savings_bytes: (total_bytes as f64 * mean) as u64,
+        estimated_savings_percent: (mean * 100.0) as f32,
+        confidence_interval_percent: (confidence * 100.0) as f32,
+        sample_size: ratios.len(),
+    })
+}
+
+/// Quick savings guess with the default options from at most 10 files, for showing before
+/// the user has picked any settings. Estimates 0 when none of the files can be sampled.
+#[tauri::command]
+async fn get_compression_estimate(paths: Vec<String>) -> EstimateResult {
+    let (total_bytes, mean) = match sample_savings_ratios(&paths, &CompressionOptions::default(), 10) {
+        Ok((total_bytes, ratios)) => (total_bytes, ratios.iter().sum::<f64>() / ratios.len() as f64),
+        Err(_) => (0, 0.0),
+    };
+    EstimateResult {
+        total_files: paths.len(),
+        estimated_savings_bytes: (total_bytes as f64 * mean) as i64,
+        estimated_savings_pct: mean * 100.0,
+    }
+}
+
+/// Total size of the readable `paths` and the fraction saved on each of up to `max_samples`
+/// of them, compressed in memory. Never empty.
+fn sample_savings_ratios(
+    paths: &[String],
+    options: &CompressionOptions,
+    max_samples: usize,
+) -> Result<(u64, Vec<f64>), CompressionError> {
+    let mut sized: Vec<(u64, &String)> = paths
+        .iter()
+        .filter_map(|p| fs::metadata(p).ok().map(|m| (m.len(), p)))
+        .collect();
+    if sized.is_empty() {
+        return Err(CompressionError::Validation(vec!["no readable files to sample".to_string()]));
+    }
+    sized.sort_by_key(|(size, _)| *size);
+    let total_bytes: u64 = sized.iter().map(|(size, _)| size).sum();
+
+    // Pick evenly spaced files from the size-sorted list so every size decile is represented
+    let sample_size = sized.len().min(max_samples);
+    let sample: Vec<(u64, &String)> = (0..sample_size)
+        .map(|k| sized[k * sized.len() / sample_size])
+        .collect();
+
+    // Dry run: compress in memory, nothing is written
+    let ratios: Vec<f64> = sample
+        .par_iter()
+        .filter_map(|(size, path)| {
+            let compressed = compress_path_to_memory(Path::new(path), options).ok()?;
+            // Outputs that grow are never written, they save nothing
+            let saved = size.saturating_sub(compressed.len() as u64);
+            Some(if *size == 0 { 0.0 } else { saved as f64 / *size as f64 })
+        })
+        .collect();
+    if ratios.is_empty() {
+        return Err(CompressionError::Encode("none of the sampled files could be compressed".to_string()));
+    }
+    Ok((total_bytes, ratios))
+}
+
+/// Compress a file in memory and return the result base64-encoded, nothing is written.
+#[tauri::command]
+async fn compress_to_buffer(
+    path: String,
+    options: CompressionOptions,
+    max_buffer_return_bytes: Option<u64>,
+) -> Result<String, CompressionError> {
+    options.validate()?;
+    let data = compress_path_to_memory(Path::new(&path), &options)?;
+    // Everything goes through JSON IPC, refuse outputs that would blow up the webview
+    if let Some(max) = max_buffer_return_bytes {
+        if data.len() as u64 > max {
+            return Err(CompressionError::Validation(vec![format!(
+                "compressed output is {} bytes, over the {} byte limit",
+                data.len(),
+                max
+            )]));
+        }
+    }
+    Ok(base64::engine::general_purpose::STANDARD.encode(&data))
+}
+
+/// Compress a base64-encoded image of type `extension` and return the result base64-encoded,
+/// for callers that don't have the image on disk. The input comes back unchanged when
+/// recompressing it wouldn't make it smaller.
+#[tauri::command]
+async fn compress_data(
+    data: String,
+    extension: String,
+    options: CompressionOptions,
+) -> Result<String, CompressionError> {
+    options.validate()?;
+    let input_data = base64::engine::general_purpose::STANDARD
+        .decode(data.trim())
+        .map_err(|e| CompressionError::Decode(format!("invalid base64: {}", e)))?;
+    let format = match image::ImageFormat::from_extension(extension.trim_start_matches('.')) {
+        Some(f @ (image::ImageFormat::Png | image::ImageFormat::Jpeg | image::ImageFormat::Gif)) => f,
+        Some(f) if is_converted_source(f) => f,
+        _ => return Err(CompressionError::UnsupportedFormat(extension)),
+    };
+    let (compressed_data, output_format) = compress_bytes(&input_data, format, &options, &mut Vec::new())?;
+    if output_format == format && compressed_data.len() >= input_data.len() {
+        return Ok(data);
+    }
+    Ok(base64::engine::general_purpose::STANDARD.encode(&compressed_data))
+}
+
+/// Re-read a compressed file and check it matches its result.
+#[tauri::command]
+async fn validate_output(result: CompressionResult) -> Result<ValidationReport, CompressionError> {
+    Ok(validate_results(&[result]))
+}
+
+#[tauri::command]
+async fn validate_batch(results: Vec<CompressionResult>) -> Result<ValidationReport, CompressionError> {
+    tauri::async_runtime::spawn_blocking(move || validate_results(&results))
+        .await
+        .map_err(|e| CompressionError::Io(std::io::Error::other(e.to_string())))
+}
+
+/// Dry-run `path` in lossless and lossy mode, so the frontend can recommend a mode before
+/// a batch is started. Nothing is written.
+#[tauri::command]
+async fn compare_compression_modes(path: String, quality: u8) -> Result<ComparisonResult, CompressionError> {
+    let options = CompressionOptions {
+        quality,
+        dry_run: true,
+        compute_quality_metric: true,
+        // Nothing gets written, so a read-only source is fine
+        readonly_strategy: "copy_then_compress".to_string(),
+        ..Default::default()
+    };
+    options.validate()?;
+    tauri::async_runtime::spawn_blocking(move || {
+        let run = |mode: &str| {
+            let options = CompressionOptions { mode: mode.to_string(), ..options.clone() };
+            let result = process_single_file(Path::new(&path), &options);
+            match result.error {
+                Some(e) => Err(CompressionError::Encode(e)),
+                None => Ok(result),
+            }
+        };
+        let lossless = run("lossless")?;
+        let lossy = run("lossy")?;
+        Ok(ComparisonResult {
+            original_size: lossless.original_size,
+            lossless_size: lossless.compressed_size,
+            lossy_size: lossy.compressed_size,
+            lossy_ssim: lossy.quality_score,
+        })
+    })
+    .await
+    .map_err(|e| CompressionError::Io(std::io::Error::other(e.to_string())))?
+}
+
+/// Binary search the lossy PNG quality range 1-95 for the lowest quality whose output has
+/// an SSIM of at least `target_ssim` against the source, in at most 10 encodes. Nothing is
+/// written. Assumes SSIM grows with quality, which holds closely enough for a starting point.
+#[tauri::command]
+async fn find_optimal_png_quality(path: String, target_ssim: f64) -> Result<OptimalQualityResult, CompressionError> {
+    if !(target_ssim > 0.0 && target_ssim <= 1.0) {
+        return Err(CompressionError::Validation(vec![format!("target_ssim {} out of range (0, 1]", target_ssim)]));
+    }
+    tauri::async_runtime::spawn_blocking(move || {
+        let data = fs::read(&path)?;
+        if image::guess_format(&data).ok() != Some(image::ImageFormat::Png) {
+            return Err(CompressionError::UnsupportedFormat(path.clone()));
+        }
+        let img = image::load_from_memory_with_format(&data, image::ImageFormat::Png)
+            .map_err(|e| CompressionError::Decode(e.to_string()))?;
+        let mut best: Option<OptimalQualityResult> = None;
+        let mut last_miss = None;
+        let (mut low, mut high) = (1u8, 95u8);
+        for _ in 0..10 {
+            if low > high {
+                break;
+            }
+            let quality = low + (high - low) / 2;
+            let options = CompressionOptions { mode: "lossy".to_string(), quality, ..Default::default() };
+            // No minimum quality, the lossy pipeline would fall back to lossless instead of failing
+            let compressed = quantize_and_optimize(&img, &data, 0, quality, &options)?;
+            let achieved_ssim = quality_score(&data, &compressed).unwrap_or(0.0);
+            if achieved_ssim >= target_ssim {
+                best = Some(OptimalQualityResult { quality, achieved_ssim, compressed_size: compressed.len() as u64 });
+                high = quality - 1;
+            } else {
+                last_miss = Some((quality, achieved_ssim));
+                low = quality + 1;
+            }
+        }
+        best.ok_or_else(|| {
+            let (quality, achieved) = last_miss.unwrap_or((95, 0.0));
+            CompressionError::Validation(vec![format!(
+                "target_ssim {} not reached, quality {} gives {:.4}",
+                target_ssim, quality, achieved
+            )])
+        })
+    })
+    .await
+    .map_err(|e| CompressionError::Io(std::io::Error::other(e.to_string())))?
+}
+
+/// Per-pixel difference between an original and its compressed output, both decoded to RGBA.
+/// Fails when the dimensions differ, e.g. after a resize.
+#[tauri::command]
+async fn compare_files(original: String, compressed: String) -> Result<PixelDiffResult, CompressionError> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let original = open_image(Path::new(&original))?.to_rgba8();
+        let compressed = open_image(Path::new(&compressed))?.to_rgba8();
+        if original.dimensions() != compressed.dimensions() {
+            let (ow, oh) = original.dimensions();
+            let (cw, ch) = compressed.dimensions();
+            return Err(CompressionError::Validation(vec![format!(
+                "dimensions differ, {}x{} vs {}x{}",
+                ow, oh, cw, ch
+            )]));
+        }
+        let mut max_channel_delta = 0u8;
+        let mut delta_sum = 0u64;
+        let mut changed_pixel_count = 0u32;
+        for (a, b) in original.pixels().zip(compressed.pixels()) {
+            let mut changed = false;
+            for (ca, cb) in a.0.iter().zip(b.0.iter()) {
+                let delta = ca.abs_diff(*cb);
+                max_channel_delta = max_channel_delta.max(delta);
+                delta_sum += delta as u64;
+                changed |= delta != 0;
+            }
+            changed_pixel_count += changed as u32;
+        }
+        let total_pixels = original.width() * original.height();
+        let channels = total_pixels as f64 * 4.0;
+        Ok(PixelDiffResult {
+            max_channel_delta,
+            mean_absolute_error: if channels > 0.0 { delta_sum as f64 / channels } else { 0.0 },
+            changed_pixel_count,
+            total_pixels,
+        })
+    })
+    .await
+    .map_err(|e| CompressionError::Io(std::io::Error::other(e.to_string())))?
+}
+
+/// Check source files for corruption before compressing, so a broken file is reported as
+/// such instead of as a failed compression.
+#[tauri::command]
+async fn validate_files(paths: Vec<String>) -> Result<Vec<ValidationResult>, CompressionError> {
+    tauri::async_runtime::spawn_blocking(move || {
+        paths
+            .par_iter()
+            .map(|file_path| {
+                let error = check_source_file(Path::new(file_path)).err();
+                ValidationResult {
+                    file_path: file_path.clone(),
+                    valid: error.is_none(),
+                    error,
+                }
+            })
+            .collect()
+    })
+    .await
+    .map_err(|e| CompressionError::Io(std::io::Error::other(e.to_string())))
+}
+
+/// PNGs go through oxipng's fastest preset, which parses every chunk and inflates the image
+/// data; everything else is fully decoded.
+fn check_source_file(path: &Path) -> Result<(), String> {
+    match detect_format(path) {
+        Some(image::ImageFormat::Png) => {
+            let data = fs::read(path).map_err(|e| e.to_string())?;
+            oxipng::optimize_from_memory(&data, &oxipng::Options::from_preset(0))
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        }
+        Some(_) => open_image(path).map(|_| ()).map_err(|e| e.to_string()),
+        None => Err("Unsupported format".to_string()),
+    }
+}
+
+/// Write `results` as CSV for spreadsheets, one row per file.
+#[tauri::command]
+async fn export_results_csv(results: Vec<CompressionResult>, output_path: String) -> Result<(), CompressionError> {
+    use std::io::Write;
+    let mut writer = std::io::BufWriter::new(fs::File::create(&output_path)?);
+    writeln!(writer, "file_path,original_size,compressed_size,saved_bytes,compression_ratio,status,error")?;
+    for result in &results {
+        writeln!(
+            writer,
+            "{},{},{},{},{},{},{}",
+            csv_field(&result.file_path),
+            result.original_size,
+            result.compressed_size,
+            result.saved_before,
+            result.compression_ratio,
+            csv_field(&result.status),
+            csv_field(result.error.as_deref().unwrap_or("")),
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Quote a CSV field when it contains a separator, quote or line break (RFC 4180).
+fn csv_field(value: &str) -> std::borrow::Cow<'_, str> {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\"")).into()
+    } else {
+        value.into()
+    }
+}
+
+#[tauri::command]
+async fn compress_clipboard_image(
+    _app: tauri::AppHandle,
+    options: CompressionOptions,
+    output_path: String,
+) -> Result<CompressionResult, CompressionError> {
+    options.validate()?;
+    // Clipboard access blocks, and on macOS it has to be serialized with the main thread's
+    // pasteboard access, so keep it off the async runtime
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        compress_clipboard_to(&output_path, &options)
+    })
+    .await
+    .map_err(|e| CompressionError::Io(std::io::Error::other(e.to_string())))??;
+    Ok(result)
+}
+
+fn compress_clipboard_to(
+    output_path: &str,
+    options: &CompressionOptions,
+) -> Result<CompressionResult, CompressionError> {
+    let image_data = arboard::Clipboard::new()
+        .and_then(|mut clipboard| clipboard.get_image())
+        .map_err(|e| CompressionError::Decode(format!("no image on the clipboard: {}", e)))?;
+    let rgba = image::RgbaImage::from_raw(
+        image_data.width as u32,
+        image_data.height as u32,
+        image_data.bytes.into_owned(),
+    )
+    .ok_or_else(|| CompressionError::Decode("clipboard image has an invalid buffer size".to_string()))?;
+    // There is no source file, so measure savings against the raw bitmap
+    let original_size = rgba.as_raw().len() as u64;
+    let img = image::DynamicImage::ImageRgba8(rgba);
+
+    let path = Path::new(output_path);
+    let format = match path
+        .extension()
+        .and_then(|s| s.to_str())
+        .and_then(image::ImageFormat::from_extension)
+    {
+        Some(f @ (image::ImageFormat::Png | image::ImageFormat::Jpeg)) => f,
+        _ => return Err(CompressionError::UnsupportedFormat(output_path.to_string())),
+    };
+
+    // The PNG pipelines optimize encoded bytes, so start them from a plain PNG encode
+    let mut input_data = Vec::new();
+    if format == image::ImageFormat::Png {
+        img.write_to(&mut std::io::Cursor::new(&mut input_data), image::ImageOutputFormat::Png)
+            .map_err(|e| CompressionError::Encode(format!("PNG encoding failed: {}", e)))?;
+    }
+
+    let mut notes = Vec::new();
+    let compressed_data = compress_image(&input_data, Some(img), format, options, &mut notes)?;
+    let (write_result, retry_delays) = retry_io(options, is_network_path(path), || match fs::metadata(path) {
+        // Overwriting goes through a temp file like in-place compression, keeping the file's mode
+        Ok(existing) if options.atomic_write => write_atomically(path, &compressed_data, &existing.permissions()),
+        _ => fs::write(path, &compressed_data),
+    });
+    if !retry_delays.is_empty() {
+        notes.push(format!("retry_delays_ms: {:?}", retry_delays));
+    }
+    write_result?;
+
+    let compressed_size = compressed_data.len() as u64;
+    Ok(CompressionResult {
+        file_path: output_path.to_string(),
+        original_size,
+        compressed_size,
+        saved_before: original_size.saturating_sub(compressed_size),
+        status: "success".to_string(),
+        error: None,
+        notes,
+        ..Default::default()
+    })
+}
+
+/// Set the source's mtime on a written file. The data is already saved, so a failure
+/// here only gets a note.
+fn restore_mtime(path: &Path, source: &fs::Metadata, notes: &mut Vec<String>) {
+    let restored = source
+        .modified()
+        .and_then(|mtime| filetime::set_file_mtime(path, filetime::FileTime::from_system_time(mtime)));
+    if let Err(e) = restored {
+        notes.push(format!("preserve_timestamps_failed: {}", e));
+    }
+}
+
+/// Set the source's mode bits on a written file, like `restore_mtime` a failure only gets
+/// a note. Nothing to do outside Unix.
+#[cfg(unix)]
+fn restore_permissions(path: &Path, source: &fs::Metadata, notes: &mut Vec<String>) {
+    use std::os::unix::fs::PermissionsExt;
+    let mode = source.permissions().mode();
+    if let Err(e) = fs::set_permissions(path, fs::Permissions::from_mode(mode)) {
+        notes.push(format!("preserve_permissions_failed: {}", e));
+    }
+}
+
+#[cfg(not(unix))]
+fn restore_permissions(_path: &Path, _source: &fs::Metadata, _notes: &mut Vec<String>) {}
+
+fn process_single_file(path: &Path, options: &CompressionOptions) -> CompressionResult {
+    let started = std::time::Instant::now();
+    let mut result = compress_single_file(path, options);
+    result.duration_ms = Some(started.elapsed().as_millis() as u64);
+    result
+}
+
+/// `process_single_file` without the timing, every early return is a finished result.
+fn compress_single_file(path: &Path, options: &CompressionOptions) -> CompressionResult {
+    let file_path_str = path.to_string_lossy().to_string();
+    let mut notes: Vec<String> = Vec::new();
+
+    // fs::write follows symlinks, so compressing a link would overwrite its target
+    let is_symlink = fs::symlink_metadata(path)
+        .map(|m| m.file_type().is_symlink())
+        .unwrap_or(false);
+    if is_symlink {
+        match options.symlink_strategy.as_str() {
+            "follow" => {
+                let target = fs::read_link(path)
+                    .map(|t| t.to_string_lossy().to_string())
+                    .unwrap_or_default();
+                notes.push(format!("is_symlink_to: {}", target));
+            }
+            "error" => return create_error_result(&file_path_str, "Source is a symlink"),
+            _ => return create_skipped_result(&file_path_str, "skipped_symlink", 0),
+        }
+    }
+
+    // Get original size
+    // Removable media and network drives fail transiently on reads too, not only on writes
+    let network = is_network_path(path);
+    let (metadata, retry_delays) = retry_io(options, network, || fs::metadata(path));
+    if !retry_delays.is_empty() {
+        notes.push(format!("metadata_retry_delays_ms: {:?}", retry_delays));
+    }
+    let metadata = match metadata {
+        Ok(m) => m,
+        Err(e) => return create_error_result(&file_path_str, &e.to_string()),
+    };
+    let original_size = metadata.len();
+    let size_limit = match (options.max_file_bytes, options.min_file_bytes) {
+        (Some(max), _) if original_size > max => Some("File exceeds max_file_bytes limit"),
+        (_, Some(min)) if original_size < min => Some("File is below min_file_bytes limit"),
+        _ => None,
+    };
+    if let Some(message) = size_limit {
+        let mut result = create_skipped_result(&file_path_str, "skipped", original_size);
+        result.error = Some(message.to_string());
+        return result;
+    }
+    if let Some(threshold) = options.skip_if_modified_before {
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|d| d.as_secs());
+        if modified.is_some_and(|m| m < threshold) {
+            let mut result = create_skipped_result(&file_path_str, "skipped", original_size);
+            result.notes.push(format!("unchanged_since: {}", threshold));
+            return result;
+        }
+    }
+    // Find out now rather than after compressing, the write would only fail with PermissionDenied
+    // Only matters when the source itself gets replaced
+    let readonly = metadata.permissions().readonly() && options.output_dir.is_none();
+    if readonly {
+        match options.readonly_strategy.as_str() {
+            "skip" => return create_skipped_result(&file_path_str, "skipped_readonly", original_size),
+            "copy_then_compress" => {}
+            _ => return create_error_result(&file_path_str, "Source is read-only"),
+        }
+    }
+    let ext = path.extension().and_then(|s| s.to_str()).unwrap_or("").to_lowercase();
+
+    // Trust the magic bytes over the extension, a renamed file should still be decoded correctly
+    let ext_format = image::ImageFormat::from_extension(&ext);
+    let detected_format = detect_format(path);
+    if let (Some(expected), Some(actual)) = (ext_format, detected_format) {
+        if expected != actual {
+            notes.push(format!("format_mismatch: extension={}, actual={}", ext, format_name(actual)));
+        }
+    }
+
+    let format = match detected_format.or(ext_format) {
+        Some(f) => f,
+        None => return create_error_result(&file_path_str, "Unsupported format"),
+    };
+    // There is no AVIF decoder in this build, so an AVIF source is passed over, not failed
+    if format == image::ImageFormat::Avif {
+        let mut result = create_skipped_result(&file_path_str, "skipped_unsupported", original_size);
+        result.error = Some("AVIF sources can't be decoded, AVIF is only supported as output".to_string());
+        return result;
+    }
+    let (input_data, retry_delays) = retry_io(options, network, || fs::read(path));
+    if !retry_delays.is_empty() {
+        notes.push(format!("read_retry_delays_ms: {:?}", retry_delays));
+    }
+    let input_data = match input_data {
+        Ok(d) => d,
+        Err(e) => return create_error_result(&file_path_str, &format!("Failed to read file: {}", e)),
+    };
+    // Hash the bytes we already hold instead of reading the file a second time
+    let mut result = CompressionResult {
+        file_path: file_path_str.clone(),
+        original_size,
+        compressed_size: original_size,
+        saved_before: 0,
+        status: "success".to_string(),
+        error: None,
+        ..Default::default()
+    };
+    result.original_file_hash = options.hash_originals.then(|| sha256_hex(&input_data));
+    if options.detect_jpeg_subsampling && format == image::ImageFormat::Jpeg {
+        result.source_chroma_subsampling = jpeg_chroma_subsampling(&input_data);
+    }
+    if format == image::ImageFormat::Gif {
+        result.frame_count = gif_frame_count(&input_data);
+    }
+    if let Ok((width, height)) = image::io::Reader::with_format(std::io::Cursor::new(&input_data), format).into_dimensions() {
+        result.width = Some(width);
+        result.height = Some(height);
+        let frames = result.frame_count.unwrap_or(1).max(1) as u64;
+        result.peak_memory_bytes = Some(width as u64 * height as u64 * 4 * frames);
+    }
+
+    // Compression Logic
+    let started = std::time::Instant::now();
+    let (mut compressed_data, output_format) = match compress_bytes(&input_data, format, options, &mut notes) {
+        Ok(compressed) => compressed,
+        Err(e) => return create_error_result(&file_path_str, &e.to_string()),
+    };
+    if options.hash_originals && options.mode == "lossless" && keeps_pixels(format, output_format) {
+        result.original_pixel_hash = image::load_from_memory_with_format(&input_data, format).ok().map(|img| {
+            // Conversions turn the pixels upright on the way, see `oriented`
+            let img = if output_format != format { oriented(img, &input_data, options) } else { img };
+            pixel_hash(&img)
+        });
+    }
+    // After oxipng, which would strip the tags again
+    if options.tag_output && output_format == image::ImageFormat::Png && options.strip_mode.as_deref() != Some("all") {
+        match tag_png(&compressed_data, &options.mode) {
+            Ok(tagged) => compressed_data = tagged,
+            Err(e) => notes.push(format!("tag_output_failed: {}", e)),
+        }
+    }
+    if options.benchmark_mode != BenchmarkMode::Off {
+        result.compression_time_ms = Some(started.elapsed().as_millis() as u64);
+    }
+
+    let compressed_size = compressed_data.len() as u64;
+    let min_saving = options.min_size_saving_bytes.unwrap_or(0);
+    let smaller = compressed_size < original_size && original_size - compressed_size >= min_saving;
+    // PNG and JPEG conversions (TIFF included) are written whatever their size
+    let always_written = output_format != format
+        && matches!(output_format, image::ImageFormat::Png | image::ImageFormat::Jpeg);
+    let smaller = smaller || always_written;
+    // The target size search leaves its smallest attempt when nothing fit
+    let over_target = options.mode == "lossy"
+        && format == image::ImageFormat::Jpeg
+        && output_format == image::ImageFormat::Jpeg
+        && options.target_size_bytes.is_some_and(|target| compressed_size > target);
+    let smaller = smaller && !over_target;
+    if compressed_size < original_size && !smaller && !over_target {
+        notes.push(format!("below_min_saving: {} bytes", original_size - compressed_size));
+    }
+    if options.compute_quality_metric && smaller {
+        result.quality_score = quality_score(&input_data, &compressed_data);
+    }
+
+    println!("[Compress] {} - Original: {} -> Compressed: {}", file_path_str, original_size, compressed_size);
+
+    // Save Logic
+    // We strictly save if size is smaller.
+    // If size is larger, we keep original (effectively skipping), unless user explicitly wanted re-encode (but usually size increase is bad).
+    let writes_output = options.benchmark_mode.writes_output() && !options.dry_run;
+    let in_place = options.output_dir.is_none();
+    // With a mirror root the batch already created every output directory
+    if let (Some(dir), true, None) = (&options.output_dir, writes_output, &options.mirror_root) {
+        if let Err(e) = fs::create_dir_all(dir) {
+            return create_error_result(&file_path_str, &format!("Failed to create output directory: {}", e));
+        }
+    }
+    if smaller && writes_output {
+        let output_path = output_file_path(path, format, output_format, options);
+        // Never clobber an unrelated file next to the source, the output directory is ours
+        if in_place && output_path != path && output_path.exists() {
+            return create_error_result(
+                &file_path_str,
+                &format!("Failed to save: {} already exists", output_path.display()),
+            );
+        }
+        // A conversion leaves the source alone, only overwriting it needs a backup
+        if options.backup && output_path == path {
+            let backup = backup_file_path(path);
+            // After an earlier run the backup holds the real original, the source is its output
+            if backup.exists() {
+                notes.push(format!("backup_kept: {} already exists", backup.display()));
+            } else if let Err(e) = fs::copy(path, &backup) {
+                return create_error_result(&file_path_str, &format!("Failed to create backup: {}", e));
+            }
+            result.backup_path = Some(backup.to_string_lossy().to_string());
+        }
+        // Replacing the file by rename would turn a followed link into a regular file
+        let target = if is_symlink {
+            fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+        } else {
+            path.to_path_buf()
+        };
+        let (write_result, retry_delays) = retry_io(options, network, || {
+            if output_path != path {
+                fs::write(&output_path, &compressed_data)
+            } else if readonly {
+                replace_readonly_file(&target, &compressed_data, &metadata.permissions())
+            } else if options.atomic_write {
+                write_atomically(&target, &compressed_data, &metadata.permissions())
+            } else {
+                fs::write(path, &compressed_data)
+            }
+        });
+        if !retry_delays.is_empty() {
+            notes.push(format!("retry_delays_ms: {:?}", retry_delays));
+        }
+        if let Err(e) = write_result {
+             return create_error_result(&file_path_str, &format!("Failed to save: {}", e));
+        }
+        if readonly && output_path == path {
+            notes.push("readonly: replaced via temp copy".to_string());
+        }
+        if options.preserve_timestamps {
+            restore_mtime(&output_path, &metadata, &mut notes);
+        }
+        if options.preserve_permissions {
+            restore_permissions(&output_path, &metadata, &mut notes);
+        }
+
+        result.compressed_size = compressed_size;
+        result.saved_before = original_size.saturating_sub(compressed_size);
+        result.output_format = Some(format_name(output_format).to_string());
+        if output_path != path {
+            result.source_path = Some(file_path_str.clone());
+            result.file_path = output_path.to_string_lossy().to_string();
+        }
+    } else if writes_output && !in_place {
+        // Not smaller, but the output directory should still hold every file of the batch
+        let output_path = output_file_path(path, format, format, options);
+        if output_path != path {
+            if let Err(e) = fs::copy(path, &output_path) {
+                return create_error_result(&file_path_str, &format!("Failed to save: {}", e));
+            }
+            if options.preserve_timestamps {
+                restore_mtime(&output_path, &metadata, &mut notes);
+            }
+            result.source_path = Some(file_path_str.clone());
+            result.file_path = output_path.to_string_lossy().to_string();
+        }
+    } else if smaller {
+        // Dry run, report what would have been saved
+        if options.dry_run || options.benchmark_mode.reports_size() {
+            result.compressed_size = compressed_size;
+            result.saved_before = original_size.saturating_sub(compressed_size);
+        }
+        if !options.dry_run {
+            notes.push("benchmark: output not written".to_string());
+        }
+    }
+    // Otherwise we did not save because it got bigger or same
+    if !smaller {
+        result.status = "skipped".to_string();
+    }
+    if options.dry_run {
+        result.status = "preview".to_string();
+    }
+    if result.output_format.is_none() {
+        result.output_format = Some(format_name(format).to_string());
+    }
+    if original_size > 0 {
+        result.compression_ratio = result.compressed_size as f64 / original_size as f64;
+    }
+
+    result.notes = notes;
+    result
+}
+
+/// Validate every successful result in parallel, errored and skipped files are left out.
+fn validate_results(results: &[CompressionResult]) -> ValidationReport {
+    let checked: Vec<(String, Result<(), String>)> = results
+        .par_iter()
+        .filter(|r| r.status == "success")
+        .map(|r| (r.file_path.clone(), validate_result(r)))
+        .collect();
+
+    let mut report = ValidationReport::default();
+    for (path, outcome) in checked {
+        match outcome {
+            Ok(()) => report.valid.push(path),
+            Err(e) => report.invalid.push((path, e)),
+        }
+    }
+    report
+}
+
+/// Catch writes that looked fine but left a truncated or corrupt file, which network
+/// filesystems under load are prone to.
+fn validate_result(result: &CompressionResult) -> Result<(), String> {
+    let path = Path::new(&result.file_path);
+    let metadata = fs::metadata(path).map_err(|e| format!("File missing: {}", e))?;
+    if metadata.len() != result.compressed_size {
+        return Err(format!(
+            "Size mismatch: expected {} bytes, found {}",
+            result.compressed_size,
+            metadata.len()
+        ));
+    }
+    // There is no AVIF decoder in this build, so AVIF output only gets the size check
+    if result.output_format.as_deref() == Some("avif") {
+        return Ok(());
+    }
+    let img = open_image(path).map_err(|e| format!("Not a valid image: {}", e))?;
+    if let Some(expected) = &result.original_pixel_hash {
+        if &pixel_hash(&img) != expected {
+            return Err("Pixel hash mismatch: lossless output decodes to different pixels".to_string());
+        }
+    }
+    Ok(())
+}
+
+/// Run `input_data` through the pipeline `process_single_file` uses, `output_format` conversion
+/// included. Returns the compressed bytes and their format.
+fn compress_bytes(
+    input_data: &[u8],
+    format: image::ImageFormat,
+    options: &CompressionOptions,
+    notes: &mut Vec<String>,
+) -> Result<(Vec<u8>, image::ImageFormat), CompressionError> {
+    match options.output_image_format().filter(|target| *target != format) {
+        Some(target @ (image::ImageFormat::WebP | image::ImageFormat::Avif)) => {
+            let encoded = if target == image::ImageFormat::WebP {
+                compress_to_webp(input_data, format, options)
+            } else {
+                compress_to_avif(input_data, format, options)
+            };
+            let name = format_name(target);
+            match encoded {
+                Ok(data) if data.len() < input_data.len() => return Ok((data, target)),
+                Ok(data) => notes.push(format!("{}_not_smaller: {} bytes", name, data.len())),
+                Err(e) => notes.push(format!("{}_failed: {}", name, e)),
+            }
+        }
+        // PNG <-> JPEG, compress_image takes care of TIFF and BMP sources itself
+        Some(target) if !is_converted_source(format) => {
+            let img = image::load_from_memory_with_format(input_data, format)
+                .map_err(|e| CompressionError::Decode(e.to_string()))?;
+            return Ok((encode_as(oriented(img, input_data, options), target, options, notes)?, target));
+        }
+        _ => {}
+    }
+    let data = compress_image(input_data, None, format, options, notes)?;
+    let output_format = if is_converted_source(format) { conversion_output_format(options) } else { format };
+    Ok((data, output_format))
+}
+
+/// Compress a file without writing anything back, returning the compressed bytes.
+fn compress_path_to_memory(path: &Path, options: &CompressionOptions) -> Result<Vec<u8>, CompressionError> {
+    let input_data = fs::read(path)?;
+    let format = image::guess_format(&input_data)
+        .map_err(|_| CompressionError::UnsupportedFormat(path.to_string_lossy().to_string()))?;
+    compress_bytes(&input_data, format, options, &mut Vec::new()).map(|(data, _)| data)
+}
+
+/// Run the compression pipeline for `format` on an in-memory image.
+/// `decoded` can be passed when the caller already has the pixels, otherwise they are
+/// decoded from `input_data` only when the selected pipeline needs them.
+fn compress_image(
+    input_data: &[u8],
+    decoded: Option<image::DynamicImage>,
+    format: image::ImageFormat,
+    options: &CompressionOptions,
+    notes: &mut Vec<String>,
+) -> Result<Vec<u8>, CompressionError> {
+    let mut decoded = decoded;
+    match format {
+        image::ImageFormat::Png => {
+            println!("DEBUG: Processing mode={}, quality={}", options.mode, options.quality);
+            let mut use_quantization = match options.mode.as_str() {
+                "lossy" => true,
+                // Auto mode: images that already fit in a palette quantize without visible loss,
+                // everything else goes through the lossless pipeline
+                "auto" => {
+                    let img = take_or_decode(&mut decoded, input_data, format)?;
+                    let colors = estimate_unique_colors(&img.to_rgba8(), PALETTE_MAX_COLORS + 1);
+                    decoded = Some(img);
+                    colors <= PALETTE_MAX_COLORS
+                }
+                _ => false,
+            };
+            if use_quantization && !options.allow_bit_depth_reduction {
+                let img = take_or_decode(&mut decoded, input_data, format)?;
+                if is_high_bit_depth(&img) {
+                    notes.push("high_bit_depth: kept lossless, see allow_bit_depth_reduction".to_string());
+                    use_quantization = false;
+                }
+                decoded = Some(img);
+            }
+
+            let animated = png_chunk_names(input_data).contains(b"acTL");
+            // Quantized output is indexed whatever the source's channels, so grayscale only
+            // matters to the lossless pipeline
+            let gray = if options.allow_grayscale_conversion && !use_quantization && !animated {
+                let img = take_or_decode(&mut decoded, input_data, format)?;
+                let gray = grayscale(&img);
+                decoded = Some(img);
+                gray
+            } else {
+                None
+            };
+            if let Some(gray) = &gray {
+                let name = if gray.color().has_alpha() { "gray_alpha" } else { "gray" };
+                notes.push(format!("grayscale_conversion: {}", name));
+            }
+            let opaque = if gray.is_none() && options.color_downgrade && !animated {
+                let img = take_or_decode(&mut decoded, input_data, format)?;
+                let rgb = opaque_rgb(&img);
+                decoded = Some(img);
+                rgb
+            } else {
+                None
+            };
+            if opaque.is_some() {
+                notes.push("color_downgrade: rgba -> rgb".to_string());
+            }
+
+            if use_quantization {
+                let img = match opaque {
+                    Some(rgb) => rgb,
+                    None => take_or_decode(&mut decoded, input_data, format)?,
+                };
+                compress_png_lossy(&img, input_data, options, notes)
+            } else if let Some(reduced) = gray.or(opaque) {
+                compress_png_lossless_reduced(&reduced, input_data, options)
+            } else {
+                compress_png_lossless(input_data, options)
+            }
+        }
+        image::ImageFormat::Jpeg => {
+            let reencoded = !options.jpeg_strip_only && options.mode != "lossless";
+            let output = if options.jpeg_strip_only {
+                strip_jpeg_metadata(input_data)?
+            } else if options.mode == "lossless" {
+                // Lossless never re-encodes a JPEG, it only rewrites the entropy coding. The pixels
+                // can't be rotated that way, so EXIF is kept when it holds the orientation
+                let keep_exif = exif_orientation(input_data) != 1;
+                jpeg_lossless::optimize(input_data, keep_exif).map_err(CompressionError::Encode)?
+            } else {
+                if let (true, Some(requested)) = (options.detect_jpeg_subsampling, &options.jpeg_subsampling) {
+                    let source = jpeg_chroma_subsampling(input_data);
+                    if source.is_some_and(|source| is_subsampling_upgrade(&source, requested)) {
+                        notes.push("subsampling_upgrade_may_increase_size".to_string());
+                    }
+                }
+                let img = oriented(take_or_decode(&mut decoded, input_data, format)?, input_data, options);
+                match options.target_size_bytes {
+                    Some(target) if options.mode == "lossy" => compress_jpeg_to_target(&img, target, options, notes)?,
+                    _ => compress_jpeg(&img, options)?,
+                }
+            };
+            if options.strip_gps_exif {
+                copy_exif_without_gps(input_data, output, reencoded && options.auto_orient, notes)
+            } else {
+                Ok(output)
+            }
+        }
+        image::ImageFormat::Gif => compress_gif(input_data, options),
+        // TIFF and BMP aren't written back, they become the PNG or JPEG picked by
+        // `conversion_output_format`
+        image::ImageFormat::Tiff | image::ImageFormat::Bmp => {
+            let img = take_or_decode(&mut decoded, input_data, format)?;
+            encode_as(oriented(img, input_data, options), conversion_output_format(options), options, notes)
+        }
+        _ => Err(CompressionError::UnsupportedFormat(format_name(format).to_string())),
+    }
+}
+
+/// Source formats that are always converted to PNG or JPEG instead of being recompressed.
+fn is_converted_source(format: image::ImageFormat) -> bool {
+    matches!(format, image::ImageFormat::Tiff | image::ImageFormat::Bmp)
+}
+
+/// Output for TIFF and BMP sources: `output_format` when that is PNG or JPEG, otherwise PNG
+/// in lossless mode and JPEG in the others.
+fn conversion_output_format(options: &CompressionOptions) -> image::ImageFormat {
+    match options.output_image_format() {
+        Some(format @ (image::ImageFormat::Png | image::ImageFormat::Jpeg)) => format,
+        _ if options.mode == "lossless" => image::ImageFormat::Png,
+        _ => image::ImageFormat::Jpeg,
+    }
+}
+
+/// Encode decoded pixels as a PNG, through the PNG pipeline, or as a JPEG.
+fn encode_as(
+    img: image::DynamicImage,
+    target: image::ImageFormat,
+    options: &CompressionOptions,
+    notes: &mut Vec<String>,
+) -> Result<Vec<u8>, CompressionError> {
+    if target == image::ImageFormat::Png {
+        let mut png = Vec::new();
+        img.write_to(&mut std::io::Cursor::new(&mut png), image::ImageOutputFormat::Png)
+            .map_err(|e| CompressionError::Encode(e.to_string()))?;
+        compress_image(&png, Some(img), target, options, notes)
+    } else {
+        compress_jpeg(&img, options)
+    }
+}
+
+/// EXIF orientation (1-8) of an encoded image, 1 when it has none.
+fn exif_orientation(data: &[u8]) -> u32 {
+    exif::Reader::new()
+        .read_from_container(&mut std::io::Cursor::new(data))
+        .ok()
+        .and_then(|exif| exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY)?.value.get_uint(0))
+        .unwrap_or(1)
+}
+
+/// `img` turned upright according to the EXIF orientation of `source`, with `auto_orient`.
+fn oriented(img: image::DynamicImage, source: &[u8], options: &CompressionOptions) -> image::DynamicImage {
+    if !options.auto_orient {
+        return img;
+    }
+    match exif_orientation(source) {
+        2 => img.fliph(),
+        3 => img.rotate180(),
+        4 => img.flipv(),
+        5 => img.rotate90().fliph(),
+        6 => img.rotate90(),
+        7 => img.rotate270().fliph(),
+        8 => img.rotate270(),
+        _ => img,
+    }
+}
+
+fn take_or_decode(
+    decoded: &mut Option<image::DynamicImage>,
+    input_data: &[u8],
+    format: image::ImageFormat,
+) -> Result<image::DynamicImage, CompressionError> {
+    match decoded.take() {
+        Some(img) => Ok(img),
+        None => image::load_from_memory_with_format(input_data, format)
+            .map_err(|e| CompressionError::Decode(e.to_string())),
+    }
+}
+
+/// Advanced Lossy Compression: imagequant -> png crate -> oxipng
+fn compress_png_lossy(
+    img: &image::DynamicImage,
+    input_data: &[u8],
+    options: &CompressionOptions,
+    notes: &mut Vec<String>,
+) -> Result<Vec<u8>, CompressionError> {
+    println!("DEBUG: Starting lossy compression pipeline...");
+    let q = options.quality;
+    // Quality range logic:
+    // To give users more control:
+    // High quality (e.g. 90) -> min 60. Forces algorithm to fail if visual loss is too high.
+    // Low quality (e.g. 40) -> min 0. Allows heavy compression.
+    // This makes the slider feel more responsive to "quality requirements".
+    let (mut min_q, mut max_q) = (q.saturating_sub(30), q);
+    if let Some(w) = options.imagequant_weights {
+        notes.push(format!("imagequant_weights: {:?}", w));
+    }
+    let mut result = quantize_and_optimize(img, input_data, min_q, max_q, options);
+
+    if options.retry_on_encode_error_with_lower_quality {
+        for _ in 0..3 {
+            if !matches!(result, Err(CompressionError::QualityTooLow)) || max_q <= 20 {
+                break;
+            }
+            min_q = 0;
+            max_q = max_q.saturating_sub(20).max(20);
+            result = quantize_and_optimize(img, input_data, min_q, max_q, options);
+            match &result {
+                Ok(data) => notes.push(format!("lower_quality_retry: {}-{} -> {} bytes", min_q, max_q, data.len())),
+                Err(e) => notes.push(format!("lower_quality_retry: {}-{} -> {}", min_q, max_q, e)),
+            }
+        }
+    }
+
+    if options.auto_quality_retry {
+        // A palette that encodes larger than the source is sometimes a bad fit for the
+        // quality range, walk the range up before giving up on lossy
+        for _ in 0..3 {
+            let larger = matches!(&result, Ok(data) if data.len() > input_data.len());
+            if !larger || max_q >= 100 {
+                break;
+            }
+            min_q = (min_q + 10).min(100);
+            max_q = (max_q + 10).min(100);
+            result = quantize_and_optimize(img, input_data, min_q, max_q, options);
+            match &result {
+                Ok(data) => notes.push(format!("quality_retry: {}-{} -> {} bytes", min_q, max_q, data.len())),
+                Err(e) => notes.push(format!("quality_retry: {}-{} -> {}", min_q, max_q, e)),
+            }
+        }
+        if matches!(&result, Ok(data) if data.len() > input_data.len()) {
+            notes.push("quality_retry_fallback: lossless".to_string());
+            return compress_png_lossless(input_data, options);
+        }
+    }
+
+    match result {
+        Ok(data) => Ok(data),
+        Err(e) => {
+            println!("[Compress] Lossy failed: {}, falling back to lossless.", e);
+            // Fallback to lossless logic if quantization fails (e.g. image too simple or opaque)
+            let oxi_options = options.oxipng_options();
+            oxipng::optimize_from_memory(input_data, &oxi_options)
+                .map_err(|e| CompressionError::Encode(format!("PNG optimization failed: {}", e)))
+        }
+    }
+}
+
+/// Quantize, then run the indexed PNG through oxipng. Only fails if quantization fails.
+fn quantize_and_optimize(
+    img: &image::DynamicImage,
+    input_data: &[u8],
+    min_q: u8,
+    max_q: u8,
+    options: &CompressionOptions,
+) -> Result<Vec<u8>, CompressionError> {
+    let strip = match options.strip_chunks().unwrap_or(oxipng::StripChunks::All) {
+        oxipng::StripChunks::All if options.preserve_icc => {
+            oxipng::StripChunks::Keep([*b"iCCP"].into_iter().collect())
+        }
+        strip => strip,
+    };
+    // The quantized PNG is built from pixels only, so anything worth keeping has to be copied over
+    let color_info = match strip {
+        oxipng::StripChunks::All => None,
+        _ => png_color_info(input_data, img.width(), img.height()),
+    };
+    let data = quantize_to_png(img, min_q, max_q, options, color_info)?;
+    // Final optimization with oxipng
+    let mut oxi_options = options.oxipng_options();
+    oxi_options.strip = strip;
+    match oxipng::optimize_from_memory(&data, &oxi_options) {
+        Ok(final_data) => Ok(final_data),
+        Err(e) => {
+            println!("[Compress] Oxipng failed on quant result: {}, using lodepng raw.", e);
+            Ok(data)
+        }
+    }
+}
+
+/// Quantize to a palette with imagequant and encode as an indexed PNG.
+///
+/// imagequant has no per-channel error weights, so `imagequant_weights` are applied by scaling each
+/// channel down before quantizing (relative to the largest weight) and scaling the palette
+/// back up afterwards. A channel squeezed into fewer levels contributes less error, so the
+/// palette is spent on the heavier channels. The quality range is judged on the scaled
+/// image too, so lighter weights also let imagequant settle for fewer colors.
+fn quantize_to_png(
+    img: &image::DynamicImage,
+    min_q: u8,
+    max_q: u8,
+    options: &CompressionOptions,
+    color_info: Option<png::Info<'static>>,
+) -> Result<Vec<u8>, CompressionError> {
+    let mut img_rgba = img.to_rgba8();
+    let width = img.width() as usize;
+    let height = img.height() as usize;
+
+    let scale = options.imagequant_weights.map(|w| {
+        let max = w.iter().cloned().fold(f32::MIN, f32::max);
+        w.map(|c| c / max)
+    });
+    if let Some(scale) = scale {
+        for px in img_rgba.pixels_mut() {
+            for (c, s) in px.0.iter_mut().zip(scale) {
+                *c = (*c as f32 * s).round() as u8;
+            }
+        }
+    }
+
+    let mut attr = imagequant::Attributes::new();
+    attr.set_quality(min_q, max_q).map_err(|e| CompressionError::Encode(format!("IQ Quality err: {:?}", e)))?;
+    if let Some(size) = options.palette_size {
+        attr.set_max_colors(size as u32).map_err(|e| CompressionError::Encode(format!("IQ Colors err: {:?}", e)))?;
+    }
+
+    println!("DEBUG: ImageQuant config quality={}-{}", min_q, max_q);
+
+    // Convert raw bytes to RGBA slice using bytemuck
+    let rgba_slice: &[rgb::RGBA8] = bytemuck::cast_slice(img_rgba.as_raw());
+
+    let mut iq_image = attr.new_image_borrowed(rgba_slice, width, height, 0.0)
+        .map_err(|e| CompressionError::Encode(format!("IQ NewImage err: {:?}", e)))?;
+
+    let mut quant = attr.quantize(&mut iq_image).map_err(|e| match e {
+        imagequant::Error::QualityTooLow => CompressionError::QualityTooLow,
+        e => CompressionError::Encode(format!("IQ Quantize err: {:?}", e)),
+    })?;
+
+    quant
+        .set_dithering_level(options.dithering.unwrap_or(1.0))
+        .map_err(|e| CompressionError::Encode(format!("IQ Dither err: {:?}", e)))?;
+
+    let (mut palette, pixels): (Vec<imagequant::RGBA>, Vec<u8>) = quant.remapped(&mut iq_image)
+        .map_err(|e| CompressionError::Encode(format!("IQ Remap err: {:?}", e)))?;
+
+    if let Some([r, g, b, a]) = scale {
+        let unscale = |c: u8, s: f32| (c as f32 / s).round().min(255.0) as u8;
+        for px in &mut palette {
+            px.r = unscale(px.r, r);
+            px.g = unscale(px.g, g);
+            px.b = unscale(px.b, b);
+            px.a = unscale(px.a, a);
+        }
+    }
+
+    // Encode with png crate
+    let mut buffer = Vec::new();
+    let mut cursor = std::io::Cursor::new(&mut buffer);
+    {
+        let mut encoder = match color_info {
+            Some(info) => png::Encoder::with_info(&mut cursor, info)
+                .map_err(|e| CompressionError::Encode(format!("PNG Header err: {}", e)))?,
+            None => png::Encoder::new(&mut cursor, width as u32, height as u32),
+        };
+        encoder.set_color(png::ColorType::Indexed);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        // Prepare PLTE (RGB) and tRNS (A)
+        let mut plte: Vec<u8> = Vec::with_capacity(palette.len() * 3);
+        let mut trns: Vec<u8> = Vec::with_capacity(palette.len());
+        let mut has_transparency = false;
+
+        for px in &palette {
+            plte.push(px.r);
+            plte.push(px.g);
+            plte.push(px.b);
+            trns.push(px.a);
+            if px.a < 255 { has_transparency = true; }
+        }
+
+        encoder.set_palette(plte);
+        if has_transparency {
+            encoder.set_trns(trns);
+        }
+
+        let mut writer = encoder.write_header().map_err(|e| CompressionError::Encode(format!("PNG Header err: {}", e)))?;
+        writer.write_image_data(&pixels).map_err(|e| CompressionError::Encode(format!("PNG Write err: {}", e)))?;
+    }
+
+    Ok(buffer)
+}
+
+/// Header info for a `width`x`height` PNG carrying the color chunks of the `source` PNG.
+fn png_color_info(source: &[u8], width: u32, height: u32) -> Option<png::Info<'static>> {
+    let reader = png::Decoder::new(source).read_info().ok()?;
+    let source_info = reader.info();
+    let mut info = png::Info::with_size(width, height);
+    info.icc_profile = source_info.icc_profile.as_ref().map(|p| p.to_vec().into());
+    info.srgb = source_info.srgb;
+    info.source_gamma = source_info.source_gamma;
+    info.source_chromaticities = source_info.source_chromaticities;
+    Some(info)
+}
+
+fn compress_png_lossless(input_data: &[u8], options: &CompressionOptions) -> Result<Vec<u8>, CompressionError> {
+    let mut oxi_options = options.oxipng_options();
+    oxi_options.strip = options
+        .strip_chunks()
+        .unwrap_or_else(|| lossless_strip_chunks(input_data, options));
+    oxipng::optimize_from_memory(input_data, &oxi_options)
+        .map_err(|e| CompressionError::Encode(format!("PNG optimization failed: {}", e)))
+}
+
+/// More than 8 bits per channel, which `to_rgba8` would truncate.
+fn is_high_bit_depth(img: &image::DynamicImage) -> bool {
+    let color = img.color();
+    color.bytes_per_pixel() > color.channel_count()
+}
+
+/// The RGB version of an 8-bit RGBA image that has no transparent pixels.
+fn opaque_rgb(img: &image::DynamicImage) -> Option<image::DynamicImage> {
+    match img {
+        image::DynamicImage::ImageRgba8(rgba) if rgba.pixels().all(|px| px[3] == 255) => {
+            Some(image::DynamicImage::ImageRgb8(img.to_rgb8()))
+        }
+        _ => None,
+    }
+}
+
+/// The grayscale version of an 8-bit RGB(A) image whose pixels all have R == G == B, with
+/// alpha only when some pixel isn't opaque.
+fn grayscale(img: &image::DynamicImage) -> Option<image::DynamicImage> {
+    let rgba = match img {
+        image::DynamicImage::ImageRgb8(_) | image::DynamicImage::ImageRgba8(_) => img.to_rgba8(),
+        _ => return None,
+    };
+    if !rgba.pixels().all(|px| px[0] == px[1] && px[1] == px[2]) {
+        return None;
+    }
+    let gray = if rgba.pixels().all(|px| px[3] == 255) {
+        image::DynamicImage::ImageLuma8(image::GrayImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+            image::Luma([rgba.get_pixel(x, y)[0]])
+        }))
+    } else {
+        image::DynamicImage::ImageLumaA8(image::GrayAlphaImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+            let px = rgba.get_pixel(x, y);
+            image::LumaA([px[0], px[3]])
+        }))
+    };
+    Some(gray)
+}
+
+/// Lossless pipeline for an image already reduced to RGB or grayscale, the source's ancillary
+/// chunks are carried over and stripped the same way `compress_png_lossless` would.
+fn compress_png_lossless_reduced(
+    reduced: &image::DynamicImage,
+    input_data: &[u8],
+    options: &CompressionOptions,
+) -> Result<Vec<u8>, CompressionError> {
+    let mut oxi_options = options.oxipng_options();
+    oxi_options.strip = options
+        .strip_chunks()
+        .unwrap_or_else(|| lossless_strip_chunks(input_data, options));
+    let (color_type, pixels) = match reduced {
+        image::DynamicImage::ImageLuma8(gray) => (oxipng::ColorType::Grayscale { transparent_shade: None }, gray.as_raw().clone()),
+        image::DynamicImage::ImageLumaA8(gray) => (oxipng::ColorType::GrayscaleAlpha, gray.as_raw().clone()),
+        _ => (oxipng::ColorType::RGB { transparent_color: None }, reduced.to_rgb8().into_raw()),
+    };
+    let gray = !matches!(color_type, oxipng::ColorType::RGB { .. });
+    let mut raw = oxipng::RawImage::new(reduced.width(), reduced.height(), color_type, oxipng::BitDepth::Eight, pixels)
+        .map_err(|e| CompressionError::Encode(format!("PNG optimization failed: {}", e)))?;
+    for (name, data) in png_chunks(input_data) {
+        // Critical chunks are rebuilt, sBIT and tRNS describe the source layout, and so does
+        // bKGD once the color channels are merged
+        let layout_specific = [b"sBIT", b"tRNS"].contains(&&name) || (gray && &name == b"bKGD");
+        if ![b"IHDR", b"PLTE", b"IDAT", b"IEND"].contains(&&name) && !layout_specific {
+            raw.add_png_chunk(name, data.to_vec());
+        }
+    }
+    raw.create_optimized_png(&oxi_options)
+        .map_err(|e| CompressionError::Encode(format!("PNG optimization failed: {}", e)))
+}
+
+type Bytes4 = [u8; 4];
+
+/// The chunks oxipng's `StripChunks::Safe` keeps, which it doesn't export.
+const DISPLAY_CHUNKS: [Bytes4; 7] = [*b"cICP", *b"iCCP", *b"sRGB", *b"pHYs", *b"acTL", *b"fcTL", *b"fdAT"];
+
+/// The chunks `png_strip_unsafe_chunks_only` keeps, by chunk kind:
+/// - critical (IHDR, PLTE, IDAT, IEND): never stripped, oxipng always keeps them
+/// - color management (cICP, iCCP, sRGB, gAMA, cHRM, sBIT): change how pixels are displayed
+/// - rendering hints (tRNS, bKGD, hIST, sPLT): transparency, background and palette usage
+/// - physical size (pHYs): pixel density, only matters for printing
+/// - animation (acTL, fcTL, fdAT): APNG frames, stripping them drops the animation
+/// - metadata (tEXt, iTXt, eXIf): plain text and EXIF the user may rely on
+///
+/// Everything else is removable, including private chunks. zTXt (compressed text, often tool
+/// dumps) and tIME (leaks when the file was last edited) are left out on purpose.
+fn safe_chunk_names() -> HashSet<Bytes4> {
+    [
+        *b"IHDR", *b"PLTE", *b"IDAT", *b"IEND", *b"cICP", *b"iCCP", *b"sRGB", *b"gAMA", *b"cHRM", *b"sBIT",
+        *b"tRNS", *b"bKGD", *b"hIST", *b"sPLT", *b"pHYs", *b"acTL", *b"fcTL", *b"fdAT", *b"tEXt", *b"iTXt",
+        *b"eXIf",
+    ]
+    .into_iter()
+    .collect()
+}
+
+/// Pick the oxipng strip mode for the lossless pipeline. By default it's oxipng's safe set,
+/// minus pHYs with `strip_phys`. `png_strip_unsafe_chunks_only` keeps `safe_chunk_names`
+/// instead, minus an iCCP profile that conflicts with an sRGB chunk in the same file.
+fn lossless_strip_chunks(input_data: &[u8], options: &CompressionOptions) -> oxipng::StripChunks {
+    if options.png_strip_unsafe_chunks_only {
+        let mut keep = safe_chunk_names();
+        // sRGB takes precedence over iCCP, a file carrying both has a stale profile
+        let chunks = png_chunk_names(input_data);
+        if chunks.contains(b"iCCP") && chunks.contains(b"sRGB") {
+            keep.remove(b"iCCP");
+        }
+        if options.strip_phys {
+            keep.remove(b"pHYs");
+        }
+        oxipng::StripChunks::Keep(keep.into_iter().collect())
+    } else if options.strip_phys {
+        let keep = DISPLAY_CHUNKS.into_iter().filter(|name| name != b"pHYs").collect();
+        oxipng::StripChunks::Keep(keep)
+    } else {
+        oxipng::StripChunks::Safe
+    }
+}
+
+/// List the chunk types of a PNG byte stream in file order, without decoding anything.
+fn png_chunk_names(data: &[u8]) -> Vec<Bytes4> {
+    png_chunks(data).into_iter().map(|(name, _)| name).collect()
+}
+
+/// Every chunk of a PNG with its data, stopping at the first truncated one.
+fn png_chunks(data: &[u8]) -> Vec<(Bytes4, &[u8])> {
+    let mut chunks = Vec::new();
+    // Skip the 8 byte signature, then walk length + type + data + crc records
+    let mut pos = 8;
+    while pos + 8 <= data.len() {
+        let length = u32::from_be_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]) as usize;
+        let name = [data[pos + 4], data[pos + 5], data[pos + 6], data[pos + 7]];
+        let Some(chunk_data) = data.get(pos + 8..pos + 8 + length) else {
+            break;
+        };
+        chunks.push((name, chunk_data));
+        pos += 12 + length;
+    }
+    chunks
+}
+
+/// Add tEXt chunks naming the tool and compression mode before IEND. Tags a previous run
+/// left in the file are replaced.
+fn tag_png(data: &[u8], mode: &str) -> Result<Vec<u8>, png::EncodingError> {
+    use png::text_metadata::{EncodableTextChunk, TEXtChunk};
+    let tags = [
+        TEXtChunk::new("Software", format!("pngCompress v{}", env!("CARGO_PKG_VERSION"))),
+        TEXtChunk::new("CompressionMode", mode),
+    ];
+    let mut output = data[..8.min(data.len())].to_vec();
+    let mut pos = 8;
+    while pos + 8 <= data.len() {
+        let length = u32::from_be_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]) as usize;
+        let Some(record) = data.get(pos..pos + 12 + length) else {
+            break;
+        };
+        let name = &record[4..8];
+        let stale_tag = name == b"tEXt" && tags.iter().any(|tag| {
+            record[8..8 + length].starts_with(tag.keyword.as_bytes()) && record.get(8 + tag.keyword.len()) == Some(&0)
+        });
+        if name == b"IEND" {
+            for tag in &tags {
+                tag.encode(&mut output)?;
+            }
+        }
+        if !stale_tag {
+            output.extend_from_slice(record);
+        }
+        pos += 12 + length;
+    }
+    Ok(output)
+}
+
+/// Whether re-encoding with `requested` subsampling keeps more chroma than the `source` has,
+/// which only costs bytes since the detail is already gone.
+fn is_subsampling_upgrade(source: &str, requested: &str) -> bool {
+    // Chroma samples per 4 luma samples
+    let resolution = |subsampling: &str| match subsampling {
+        "4:4:4" => Some(4),
+        "4:2:2" | "4:4:0" => Some(2),
+        "4:2:0" | "4:1:1" => Some(1),
+        "4:0:0" => Some(0),
+        _ => None,
+    };
+    match (resolution(source), resolution(requested)) {
+        (Some(source), Some(requested)) => requested > source,
+        _ => false,
+    }
+}
+
+/// Read the chroma subsampling from a JPEG's frame header.
+/// jpeg-decoder only reports the pixel format, so the SOF marker is parsed directly.
+fn jpeg_chroma_subsampling(data: &[u8]) -> Option<String> {
+    let mut pos = 2; // skip SOI
+    while pos + 4 <= data.len() {
+        if data[pos] != 0xFF {
+            return None;
+        }
+        let marker = data[pos + 1];
+        let length = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        // SOF0-SOF15, except DHT (C4), JPG (C8) and DAC (CC) which share the range
+        if (0xC0..=0xCF).contains(&marker) && ![0xC4, 0xC8, 0xCC].contains(&marker) {
+            let frame = data.get(pos + 4..pos + 2 + length)?;
+            let components = *frame.get(5)? as usize;
+            if components == 1 {
+                return Some("4:0:0".to_string());
+            }
+            // Each component is id, sampling factors (h << 4 | v), quantization table
+            let sampling = |i: usize| frame.get(6 + i * 3 + 1).map(|f| (f >> 4, f & 0x0F));
+            let (luma_h, luma_v) = sampling(0)?;
+            let (chroma_h, chroma_v) = sampling(1)?;
+            if chroma_h == 0 || chroma_v == 0 {
+                return None;
+            }
+            let name = match (luma_h / chroma_h, luma_v / chroma_v) {
+                (1, 1) => "4:4:4",
+                (2, 1) => "4:2:2",
+                (2, 2) => "4:2:0",
+                (1, 2) => "4:4:0",
+                (4, 1) => "4:1:1",
+                _ => return Some(format!("{}x{}", luma_h / chroma_h, luma_v / chroma_v)),
+            };
+            return Some(name.to_string());
+        }
+        // Start of scan, no frame header found before the image data
+        if marker == 0xDA {
+            return None;
+        }
+        pos += 2 + length;
+    }
+    None
+}
+
+/// Re-encode as WebP with libwebp, lossless in lossless mode and at `quality` otherwise,
+/// spending `webp_method` effort.
+fn compress_to_webp(
+    input_data: &[u8],
+    format: image::ImageFormat,
+    options: &CompressionOptions,
+) -> Result<Vec<u8>, CompressionError> {
+    let img = image::load_from_memory_with_format(input_data, format)
+        .map_err(|e| CompressionError::Decode(e.to_string()))?;
+    let img = oriented(img, input_data, options).to_rgba8();
+    let encoder = webp::Encoder::from_rgba(img.as_raw(), img.width(), img.height());
+    let mut config = webp::WebPConfig::new()
+        .map_err(|_| CompressionError::Encode("WebP config init failed".to_string()))?;
+    let lossless = options.mode == "lossless";
+    config.lossless = lossless as i32;
+    config.alpha_compression = !lossless as i32;
+    // In lossless mode libwebp reads quality as effort, 75 is what `encode_lossless` uses
+    config.quality = if lossless { 75.0 } else { options.quality as f32 };
+    config.method = options.webp_method.unwrap_or(4) as i32;
+    let data = encoder
+        .encode_advanced(&config)
+        .map_err(|e| CompressionError::Encode(format!("WebP encoding failed: {:?}", e)))?;
+    Ok(data.to_vec())
+}
+
+/// Re-encode as AVIF with ravif, trading encoder speed for size as `quality` goes up.
+fn compress_to_avif(
+    input_data: &[u8],
+    format: image::ImageFormat,
+    options: &CompressionOptions,
+) -> Result<Vec<u8>, CompressionError> {
+    let img = image::load_from_memory_with_format(input_data, format)
+        .map_err(|e| CompressionError::Decode(e.to_string()))?;
+    let img = oriented(img, input_data, options).to_rgba8();
+    let (width, height) = (img.width() as usize, img.height() as usize);
+    let pixels: &[rgb::RGBA8] = bytemuck::cast_slice(img.as_raw());
+    let quality = if options.mode == "lossless" { 100 } else { options.quality.min(100) };
+    // Speed 10 at quality 0 down to 4 at quality 100, slower speeds take minutes on large images
+    let speed = 10 - (quality as u16 * 6 / 100) as u8;
+    let encoded = ravif::Encoder::new()
+        .with_quality(quality as f32)
+        .with_speed(speed)
+        .encode_rgba(ravif::Img::new(pixels, width, height))
+        .map_err(|e| CompressionError::Encode(format!("AVIF encoding failed: {}", e)))?;
+    Ok(encoded.avif_file)
+}
+
+/// The directory in `output_dir` that `path` is written to: its parent's place below `root`,
+/// or `output_dir` itself without a root.
+fn mirrored_dir(path: &Path, output_dir: &Path, root: Option<&Path>) -> PathBuf {
+    let relative = root.and_then(|root| path.parent()?.strip_prefix(root).ok());
+    match relative {
+        Some(relative) => output_dir.join(relative),
+        None => output_dir.to_path_buf(),
+    }
+}
+
+/// Deepest directory containing every path, `None` when they share none (or are a mix of
+/// relative and absolute paths).
+fn common_parent(paths: &[String]) -> Option<PathBuf> {
+    let mut parents = paths.iter().filter_map(|p| Path::new(p).parent());
+    let mut common = parents.next()?.to_path_buf();
+    for parent in parents {
+        while !parent.starts_with(&common) {
+            if !common.pop() {
+                return None;
+            }
+        }
+    }
+    (!common.as_os_str().is_empty()).then_some(common)
+}
+
+/// Create every directory the batch writes to in `output_dir` for `mirror_directory_structure`,
+/// in parallel, stopping at the first one that can't be created.
+fn pre_create_output_dirs(paths: &[String], output_dir: &str) -> Result<(), String> {
+    let root = common_parent(paths);
+    let directories: HashSet<PathBuf> = paths
+        .iter()
+        .map(|p| mirrored_dir(Path::new(p), Path::new(output_dir), root.as_deref()))
+        .collect();
+    directories.par_iter().try_for_each(|dir| {
+        fs::create_dir_all(dir).map_err(|e| format!("Failed to create output directory {}: {}", dir.display(), e))
+    })
+}
+
+/// Record the batch root and create the output directories before any worker starts, when
+/// `mirror_directory_structure` applies.
+fn prepare_mirrored_output(paths: &[String], options: &mut CompressionOptions) -> Result<(), CompressionError> {
+    let Some(output_dir) = options.output_dir.clone() else {
+        return Ok(());
+    };
+    if !options.mirror_directory_structure {
+        return Ok(());
+    }
+    if options.benchmark_mode.writes_output() && !options.dry_run {
+        pre_create_output_dirs(paths, &output_dir).map_err(|e| CompressionError::Io(std::io::Error::other(e)))?;
+    }
+    options.mirror_root = common_parent(paths);
+    Ok(())
+}
+
+fn backup_file_path(path: &Path) -> PathBuf {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(".orig");
+    PathBuf::from(backup)
+}
+
+/// Where the compressed file is written: the source path, or the same file name in
+/// `output_dir` (below its mirrored directory with `mirror_root`), with the extension
+/// changed when converting to another format.
+fn output_file_path(
+    path: &Path,
+    source_format: image::ImageFormat,
+    output_format: image::ImageFormat,
+    options: &CompressionOptions,
+) -> PathBuf {
+    let base = match (&options.output_dir, path.file_name()) {
+        (Some(dir), Some(name)) => mirrored_dir(path, Path::new(dir), options.mirror_root.as_deref()).join(name),
+        _ => path.to_path_buf(),
+    };
+    if output_format != source_format {
+        base.with_extension(format_name(output_format))
+    } else {
+        base
+    }
+}
+
+fn compress_jpeg(img: &image::DynamicImage, options: &CompressionOptions) -> Result<Vec<u8>, CompressionError> {
+    let quality = if options.mode == "lossless" { 100 } else { options.quality };
+    compress_jpeg_at(img, quality, options)
+}
+
+/// Binary search for the highest JPEG quality between `quality` and 95 that fits in `target`
+/// bytes, in at most 8 encodes. When nothing fits the output at the lowest quality tried is returned.
+fn compress_jpeg_to_target(
+    img: &image::DynamicImage,
+    target: u64,
+    options: &CompressionOptions,
+    notes: &mut Vec<String>,
+) -> Result<Vec<u8>, CompressionError> {
+    let (mut low, mut high) = (options.quality.clamp(1, 95), 95);
+    let mut best: Option<(u8, Vec<u8>)> = None;
+    // Each miss lowers `high`, so the last one is the lowest quality tried
+    let mut last_miss: Option<(u8, Vec<u8>)> = None;
+    for _ in 0..8 {
+        if low > high {
+            break;
+        }
+        let quality = low + (high - low) / 2;
+        let data = compress_jpeg_at(img, quality, options)?;
+        if data.len() as u64 <= target {
+            best = Some((quality, data));
+            low = quality + 1;
+        } else {
+            last_miss = Some((quality, data));
+            if quality == 1 {
+                break;
+            }
+            high = quality - 1;
+        }
+    }
+    match (best, last_miss) {
+        (Some((quality, data)), _) => {
+            notes.push(format!("target_size_quality: {}", quality));
+            Ok(data)
+        }
+        (None, Some((quality, data))) => {
+            notes.push(format!("target_size_not_reached: {} bytes at quality {}", data.len(), quality));
+            Ok(data)
+        }
+        (None, None) => compress_jpeg(img, options),
+    }
+}
+
+fn compress_jpeg_at(img: &image::DynamicImage, quality: u8, options: &CompressionOptions) -> Result<Vec<u8>, CompressionError> {
+    println!("DEBUG: JPEG compression with quality={}", quality);
+
+    // Chroma "pixel" size per luma pixel for Cb and Cr, grayscale has no chroma to subsample
+    let chroma = match options.jpeg_subsampling.as_deref() {
+        Some("4:4:4") => (1, 1),
+        Some("4:2:2") => (2, 1),
+        _ => (2, 2),
+    };
+    let (pixels, color_space) = match img {
+        image::DynamicImage::ImageLuma8(gray) => (gray.as_raw().clone(), mozjpeg::ColorSpace::JCS_GRAYSCALE),
+        _ => (img.to_rgb8().into_raw(), mozjpeg::ColorSpace::JCS_RGB),
+    };
+
+    // mozjpeg reports libjpeg errors by panicking
+    std::panic::catch_unwind(|| -> std::io::Result<Vec<u8>> {
+        let mut compress = mozjpeg::Compress::new(color_space);
+        // Resets all parameters, so it comes before everything else
+        if options.jpeg_progressive {
+            compress.set_scan_optimization_mode(mozjpeg::ScanMode::AllComponentsTogether);
+            compress.set_progressive_mode();
+        } else {
+            compress.set_optimize_scans(false);
+        }
+        compress.set_size(img.width() as usize, img.height() as usize);
+        compress.set_quality(quality as f32);
+        if color_space != mozjpeg::ColorSpace::JCS_GRAYSCALE {
+            compress.set_chroma_sampling_pixel_sizes(chroma, chroma);
+        }
+        let mut started = compress.start_compress(Vec::new())?;
+        started.write_scanlines(&pixels)?;
+        started.finish()
+    })
+    .map_err(|_| CompressionError::Encode("JPEG encoding failed: libjpeg error".to_string()))?
+    .map_err(|e| CompressionError::Encode(format!("JPEG encoding failed: {}", e)))
+}
+
+/// Re-encode a GIF frame by frame, keeping each frame's position, delay and disposal.
+/// Lossy quantizes every frame to its own palette with imagequant, lossless only drops
+/// unused palette entries and re-compresses the LZW data.
+fn compress_gif(input_data: &[u8], options: &CompressionOptions) -> Result<Vec<u8>, CompressionError> {
+    let lossy = options.mode != "lossless";
+    let mut decode_options = gif::DecodeOptions::new();
+    decode_options.set_color_output(if lossy { gif::ColorOutput::RGBA } else { gif::ColorOutput::Indexed });
+    let mut decoder = decode_options
+        .read_info(input_data)
+        .map_err(|e| CompressionError::Decode(e.to_string()))?;
+    let (width, height, repeat) = (decoder.width(), decoder.height(), decoder.repeat());
+    let global_palette = decoder.global_palette().map(|p| p.to_vec());
+
+    let mut frames = Vec::new();
+    while let Some(frame) = decoder
+        .read_next_frame()
+        .map_err(|e| CompressionError::Decode(e.to_string()))?
+    {
+        frames.push(frame.clone());
+    }
+
+    let (global_palette, frames) = if lossy {
+        let frames = frames
+            .into_iter()
+            .map(|frame| quantize_gif_frame(frame, options))
+            .collect::<Result<Vec<_>, _>>()?;
+        (Vec::new(), frames)
+    } else {
+        compact_gif_palettes(global_palette, frames)
+    };
+
+    let mut buffer = Vec::new();
+    {
+        let mut encoder = gif::Encoder::new(&mut buffer, width, height, &global_palette)
+            .map_err(|e| CompressionError::Encode(format!("GIF header err: {}", e)))?;
+        if frames.len() > 1 {
+            encoder
+                .set_repeat(repeat)
+                .map_err(|e| CompressionError::Encode(format!("GIF repeat err: {}", e)))?;
+        }
+        for frame in &frames {
+            encoder
+                .write_frame(frame)
+                .map_err(|e| CompressionError::Encode(format!("GIF frame err: {}", e)))?;
+        }
+    }
+    Ok(buffer)
+}
+
+/// Quantize one RGBA frame. GIF has a single transparent index, so alpha is made binary first
+/// and imagequant then folds every transparent pixel into one palette entry.
+/// No minimum quality, a frame that can't reach it would fail the whole animation.
+fn quantize_gif_frame(
+    mut frame: gif::Frame<'static>,
+    options: &CompressionOptions,
+) -> Result<gif::Frame<'static>, CompressionError> {
+    let mut rgba = frame.buffer.to_vec();
+    for px in rgba.chunks_exact_mut(4) {
+        px[3] = if px[3] < 128 { 0 } else { 255 };
+    }
+    let pixels: &[rgb::RGBA8] = bytemuck::cast_slice(&rgba);
+
+    let mut attr = imagequant::Attributes::new();
+    attr.set_quality(0, options.quality).map_err(|e| CompressionError::Encode(format!("IQ Quality err: {:?}", e)))?;
+    if let Some(size) = options.palette_size {
+        attr.set_max_colors(size as u32).map_err(|e| CompressionError::Encode(format!("IQ Colors err: {:?}", e)))?;
+    }
+    let mut iq_image = attr
+        .new_image_borrowed(pixels, frame.width as usize, frame.height as usize, 0.0)
+        .map_err(|e| CompressionError::Encode(format!("IQ NewImage err: {:?}", e)))?;
+    let mut quant = attr
+        .quantize(&mut iq_image)
+        .map_err(|e| CompressionError::Encode(format!("IQ Quantize err: {:?}", e)))?;
+    quant
+        .set_dithering_level(options.dithering.unwrap_or(1.0))
+        .map_err(|e| CompressionError::Encode(format!("IQ Dither err: {:?}", e)))?;
+    let (palette, indices) = quant
+        .remapped(&mut iq_image)
+        .map_err(|e| CompressionError::Encode(format!("IQ Remap err: {:?}", e)))?;
+
+    frame.transparent = palette.iter().position(|px| px.a == 0).map(|i| i as u8);
+    frame.palette = Some(palette.iter().flat_map(|px| [px.r, px.g, px.b]).collect());
+    frame.buffer = indices.into();
+    Ok(frame)
+}
+
+/// Drop palette entries no pixel refers to, shared across the frames using the global palette.
+fn compact_gif_palettes(
+    global_palette: Option<Vec<u8>>,
+    mut frames: Vec<gif::Frame<'static>>,
+) -> (Vec<u8>, Vec<gif::Frame<'static>>) {
+    fn mark_used(frame: &gif::Frame, used: &mut [bool; 256]) {
+        for &index in frame.buffer.iter() {
+            used[index as usize] = true;
+        }
+        if let Some(index) = frame.transparent {
+            used[index as usize] = true;
+        }
+    }
+    fn compact(palette: &[u8], used: &[bool; 256]) -> (Vec<u8>, [u8; 256]) {
+        let mut compacted = Vec::with_capacity(palette.len());
+        let mut remap = [0u8; 256];
+        for (index, rgb) in palette.chunks_exact(3).enumerate().take(256) {
+            if used[index] {
+                remap[index] = (compacted.len() / 3) as u8;
+                compacted.extend_from_slice(rgb);
+            }
+        }
+        (compacted, remap)
+    }
+    fn apply(frame: &mut gif::Frame, remap: &[u8; 256]) {
+        for index in frame.buffer.to_mut().iter_mut() {
+            *index = remap[*index as usize];
+        }
+        frame.transparent = frame.transparent.map(|index| remap[index as usize]);
+    }
+
+    let mut global = Vec::new();
+    if let Some(palette) = global_palette {
+        let mut used = [false; 256];
+        for frame in frames.iter().filter(|f| f.palette.is_none()) {
+            mark_used(frame, &mut used);
+        }
+        let (compacted, remap) = compact(&palette, &used);
+        for frame in frames.iter_mut().filter(|f| f.palette.is_none()) {
+            apply(frame, &remap);
+        }
+        global = compacted;
+    }
+    for frame in frames.iter_mut() {
+        if let Some(palette) = frame.palette.take() {
+            let mut used = [false; 256];
+            mark_used(frame, &mut used);
+            let (compacted, remap) = compact(&palette, &used);
+            apply(frame, &remap);
+            frame.palette = Some(compacted);
+        }
+    }
+    (global, frames)
+}
+
+/// Count the frames of a GIF without decoding their pixels.
+fn gif_frame_count(data: &[u8]) -> Option<u32> {
+    let mut options = gif::DecodeOptions::new();
+    options.skip_frame_decoding(true);
+    let mut decoder = options.read_info(data).ok()?;
+    let mut count = 0;
+    while let Ok(Some(_)) = decoder.next_frame_info() {
+        count += 1;
+    }
+    Some(count)
+}
+
+fn create_error_result(path: &str, error: &str) -> CompressionResult {
+    CompressionResult {
+        file_path: path.to_string(),
+        original_size: 0,
+        compressed_size: 0,
+        saved_before: 0,
+        status: "error".to_string(),
+        error: Some(error.to_string()),
+        ..Default::default()
+    }
+}
+
+fn create_skipped_result(path: &str, status: &str, size: u64) -> CompressionResult {
+    CompressionResult {
+        file_path: path.to_string(),
+        original_size: size,
+        compressed_size: size,
+        saved_before: 0,
+        status: status.to_string(),
+        error: None,
+        compression_ratio: 1.0,
+        ..Default::default()
+    }
+}
+
+/// Write `data` to a temp file in `path`'s directory and rename it over `path`. The temp file
+/// gets `permissions` first, it is created owner-only.
+fn write_atomically(path: &Path, data: &[u8], permissions: &fs::Permissions) -> std::io::Result<()> {
+    use std::io::Write;
+    let dir = path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let mut temp = tempfile::NamedTempFile::new_in(dir)?;
+    temp.write_all(data)?;
+    temp.as_file().sync_all()?;
+    fs::set_permissions(temp.path(), permissions.clone())?;
+    temp.persist(path).map_err(|e| e.error)?;
+    Ok(())
+}
+
+/// Replace a read-only file without ever making it writable in place: the data is written to
+/// a temp file, the original removed and the temp copied over, then the permissions restored.
+fn replace_readonly_file(path: &Path, data: &[u8], permissions: &fs::Permissions) -> std::io::Result<()> {
+    let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    let temp = std::env::temp_dir().join(format!("pngcompress-{}-{}", std::process::id(), name));
+    fs::write(&temp, data)?;
+
+    // Unix only needs write access to the directory, Windows refuses to delete read-only files
+    #[cfg(windows)]
+    {
+        let mut writable = permissions.clone();
+        writable.set_readonly(false);
+        fs::set_permissions(path, writable)?;
+    }
+    if let Err(e) = fs::remove_file(path) {
+        let _ = fs::remove_file(&temp);
+        return Err(e);
+    }
+    if let Err(e) = fs::copy(&temp, path) {
+        // The original is gone at this point, leave the temp copy for the user to recover
+        return Err(std::io::Error::new(
+            e.kind(),
+            format!("{} (compressed data kept at {})", e, temp.display()),
+        ));
+    }
+    let _ = fs::remove_file(&temp);
+    fs::set_permissions(path, permissions.clone())
+}
+
+/// Run an I/O operation, retrying up to `max_retries` times. A missing file is not
+/// transient, so NotFound is returned right away.
+/// Returns the result of the last attempt and the delays slept before each retry.
+fn retry_io<T>(
+    options: &CompressionOptions,
+    network: bool,
+    mut op: impl FnMut() -> std::io::Result<T>,
+) -> (std::io::Result<T>, Vec<u64>) {
+    let max_retries = options.max_retries.unwrap_or(0);
+    let mut delays = Vec::new();
+    let mut attempt = 0;
+    loop {
+        match op() {
+            Err(e) if attempt < max_retries && e.kind() != std::io::ErrorKind::NotFound => {
+                let delay = retry_delay_ms(options, attempt, network);
+                println!("[Compress] I/O failed: {}, retrying in {} ms", e, delay);
+                std::thread::sleep(std::time::Duration::from_millis(delay));
+                delays.push(delay);
+                attempt += 1;
+            }
+            result => return (result, delays),
+        }
+    }
+}
+
+fn retry_delay_ms(options: &CompressionOptions, attempt: u8, network: bool) -> u64 {
+    let base = options.retry_delay_ms;
+    if options.backoff_network_only && !network {
+        return base;
+    }
+    // Transient failures take a while to clear, back off 1x, 2x, 4x, 8x
+    (base << attempt.min(3)).min(base * 8)
+}
+
+/// Best-effort guess whether a path lives on a network filesystem (SMB/NFS mounts).
+fn is_network_path(path: &Path) -> bool {
+    let path_str = path.to_string_lossy();
+    if path_str.starts_with("\\\\") || path_str.starts_with("/Volumes/") {
+        return true;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        // A file on a different device than the root filesystem is likely a mount
+        if let (Ok(file), Ok(root)) = (fs::metadata(path), fs::metadata("/")) {
+            return file.dev() != root.dev();
+        }
+    }
+    false
+}
+
+/// Remove metadata segments from a JPEG without touching the entropy-coded data, see
+/// `jpeg_strip_only` for what is kept.
+fn strip_jpeg_metadata(data: &[u8]) -> Result<Vec<u8>, CompressionError> {
+    use img_parts::jpeg::markers;
+    let mut jpeg = img_parts::jpeg::Jpeg::from_bytes(img_parts::Bytes::copy_from_slice(data))
+        .map_err(|e| CompressionError::Decode(e.to_string()))?;
+    let keep_orientation = exif_orientation(data) != 1;
+    jpeg.segments_mut().retain(|segment| match segment.marker() {
+        markers::APP0 | markers::APP14 => true,
+        markers::APP1 if keep_orientation => segment.contents().starts_with(b"Exif\0\0"),
+        marker => !(markers::APP1..=markers::APP15).contains(&marker) && marker != markers::COM,
+    });
+    let mut output = Vec::with_capacity(data.len());
+    jpeg.encoder()
+        .write_to(&mut output)
+        .map_err(|e| CompressionError::Encode(e.to_string()))?;
+    Ok(output)
+}
+
+/// Replace the EXIF of the JPEG `output` with the EXIF of `source` minus its GPS fields, for
+/// `strip_gps_exif`. When the source EXIF can't be rewritten the output goes without EXIF
+/// rather than risk keeping the location.
+fn copy_exif_without_gps(
+    source: &[u8],
+    output: Vec<u8>,
+    upright: bool,
+    notes: &mut Vec<String>,
+) -> Result<Vec<u8>, CompressionError> {
+    use img_parts::jpeg::{markers, JpegSegment};
+    const EXIF_PREFIX: &[u8] = b"Exif\0\0";
+    let mut jpeg = img_parts::jpeg::Jpeg::from_bytes(img_parts::Bytes::from(output))
+        .map_err(|e| CompressionError::Decode(e.to_string()))?;
+    jpeg.segments_mut()
+        .retain(|segment| !(segment.marker() == markers::APP1 && segment.contents().starts_with(EXIF_PREFIX)));
+    match exif_without_gps(source, upright) {
+        Ok(Some(tiff)) => {
+            let contents = [EXIF_PREFIX, &tiff].concat();
+            // Right after SOI and the JFIF header, where readers look for it
+            let at = jpeg.segments().iter().position(|s| s.marker() != markers::APP0).unwrap_or(0);
+            jpeg.segments_mut().insert(at, JpegSegment::new_with_contents(markers::APP1, contents.into()));
+            notes.push("exif_copied_without_gps".to_string());
+        }
+        Ok(None) => {}
+        Err(e) => notes.push(format!("exif_dropped: {}", e)),
+    }
+    let mut data = Vec::new();
+    jpeg.encoder()
+        .write_to(&mut data)
+        .map_err(|e| CompressionError::Encode(e.to_string()))?;
+    Ok(data)
+}
+
+/// The TIFF-structured EXIF of `source` rewritten without the GPS IFD, the thumbnail kept.
+/// `None` when the source has no EXIF.
+fn exif_without_gps(source: &[u8], upright: bool) -> Result<Option<Vec<u8>>, String> {
+    let exif = match exif::Reader::new().read_from_container(&mut std::io::Cursor::new(source)) {
+        Ok(exif) => exif,
+        Err(exif::Error::NotFound(_)) => return Ok(None),
+        Err(e) => return Err(e.to_string()),
+    };
+    let upright_orientation = exif::Field {
+        tag: exif::Tag::Orientation,
+        ifd_num: exif::In::PRIMARY,
+        value: exif::Value::Short(vec![1]),
+    };
+    let mut writer = exif::experimental::Writer::new();
+    // The writer leaves out the GPS IFD pointer by itself once no GPS field is pushed
+    for field in exif.fields().filter(|f| f.tag.context() != exif::Context::Gps) {
+        if upright && field.tag == exif::Tag::Orientation && field.ifd_num == exif::In::PRIMARY {
+            writer.push_field(&upright_orientation);
+        } else {
+            writer.push_field(field);
+        }
+    }
+    let thumbnail_field = |tag| exif.get_field(tag, exif::In::THUMBNAIL)?.value.get_uint(0).map(|v| v as usize);
+    if let (Some(offset), Some(length)) = (
+        thumbnail_field(exif::Tag::JPEGInterchangeFormat),
+        thumbnail_field(exif::Tag::JPEGInterchangeFormatLength),
+    ) {
+        if let Some(thumbnail) = exif.buf().get(offset..offset + length) {
+            writer.set_jpeg(thumbnail, exif::In::THUMBNAIL);
+        }
+    }
+    let mut tiff = std::io::Cursor::new(Vec::new());
+    writer.write(&mut tiff, exif.little_endian()).map_err(|e| e.to_string())?;
+    let tiff = tiff.into_inner();
+    // A segment length is 16 bits and counts itself and the "Exif\0\0" prefix
+    if tiff.len() + 8 > u16::MAX as usize {
+        return Err(format!("rewritten EXIF is {} bytes, too large for one APP1 segment", tiff.len()));
+    }
+    Ok(Some(tiff))
+}
+
+/// Extract the embedded ICC profile (PNG iCCP chunk or JPEG APP2 segments).
+fn read_icc_profile(data: &[u8], format: image::ImageFormat) -> Option<Vec<u8>> {
+    match format {
+        image::ImageFormat::Png => {
+            // read_info stops at the first IDAT, iCCP has to come before it
+            let reader = png::Decoder::new(data).read_info().ok()?;
+            reader.info().icc_profile.as_ref().map(|p| p.to_vec())
+        }
+        image::ImageFormat::Jpeg => {
+            let jpeg = img_parts::jpeg::Jpeg::from_bytes(img_parts::Bytes::copy_from_slice(data)).ok()?;
+            jpeg.icc_profile().map(|p| p.to_vec())
+        }
+        _ => None,
+    }
+}
+
+/// Read the profile description from the `desc` tag (0x64657363) of an ICC profile.
+/// Handles both the v2 textDescriptionType and the v4 multiLocalizedUnicodeType layouts.
+fn icc_profile_description(profile: &[u8]) -> Option<String> {
+    let read_u32 = |at: usize| -> Option<usize> {
+        let b = profile.get(at..at + 4)?;
+        Some(u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize)
+    };
+
+    // 128 byte header, then the tag count and 12 byte (signature, offset, size) entries
+    let tag_count = read_u32(128)?.min(profile.len().saturating_sub(132) / 12);
+    let (offset, size) = (0..tag_count).find_map(|i| {
+        let entry = 132 + i * 12;
+        if profile.get(entry..entry + 4)? != b"desc" {
+            return None;
+        }
+        Some((read_u32(entry + 4)?, read_u32(entry + 8)?))
+    })?;
+    let tag = profile.get(offset..offset.checked_add(size)?)?;
+
+    let text = match tag.get(0..4)? {
+        b"desc" => {
+            let count = u32::from_be_bytes(tag.get(8..12)?.try_into().ok()?) as usize;
+            let ascii = tag.get(12..12 + count)?;
+            String::from_utf8_lossy(ascii).to_string()
+        }
+        b"mluc" => {
+            // Use the first record, strings are UTF-16BE
+            let length = u32::from_be_bytes(tag.get(20..24)?.try_into().ok()?) as usize;
+            let start = u32::from_be_bytes(tag.get(24..28)?.try_into().ok()?) as usize;
+            let units: Vec<u16> = tag
+                .get(start..start + length)?
+                .chunks_exact(2)
+                .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        _ => return None,
+    };
+    let text = text.trim_end_matches('\0').trim().to_string();
+    (!text.is_empty()).then_some(text)
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// Hex SHA-256 of an image's dimensions and RGBA pixels. Fully transparent pixels count as
+/// transparent black, lossless encoders are free to change their color.
+fn pixel_hash(img: &image::DynamicImage) -> String {
+    let mut rgba = img.to_rgba8();
+    for pixel in rgba.pixels_mut().filter(|p| p[3] == 0) {
+        *pixel = image::Rgba([0, 0, 0, 0]);
+    }
+    let mut hasher = Sha256::new();
+    hasher.update(rgba.width().to_le_bytes());
+    hasher.update(rgba.height().to_le_bytes());
+    hasher.update(rgba.as_raw());
+    format!("{:x}", hasher.finalize())
+}
+
+/// Whether lossless mode writes `output` with the exact pixels of `source`. JPEG output is
+/// only lossless as a JPEG to JPEG rewrite, and AVIF output can't be decoded to check.
+fn keeps_pixels(source: image::ImageFormat, output: image::ImageFormat) -> bool {
+    match output {
+        image::ImageFormat::Jpeg => source == image::ImageFormat::Jpeg,
+        image::ImageFormat::Avif => false,
+        _ => true,
+    }
+}
+
+/// Guess the real format of a file from its magic bytes.
+fn detect_format(path: &Path) -> Option<image::ImageFormat> {
+    image::io::Reader::open(path)
+        .ok()?
+        .with_guessed_format()
+        .ok()?
+        .format()
+}
+
+/// Read width and height from the image header only, using the content-detected format.
+fn read_dimensions(path: &Path) -> Result<(u32, u32), String> {
+    image::io::Reader::open(path)
+        .map_err(|e| e.to_string())?
+        .with_guessed_format()
+        .map_err(|e| e.to_string())?
+        .into_dimensions()
+        .map_err(|e| e.to_string())
+}
+
+/// Decode an image using its content-detected format instead of the extension.
+fn open_image(path: &Path) -> Result<image::DynamicImage, CompressionError> {
+    image::io::Reader::open(path)?
+        .with_guessed_format()?
+        .decode()
+        .map_err(|e| CompressionError::Decode(e.to_string()))
+}
+
+/// SSIM between two encoded images, `None` when either can't be decoded (AVIF) or the sizes differ.
+fn quality_score(original: &[u8], compressed: &[u8]) -> Option<f64> {
+    let original = image::load_from_memory(original).ok()?.to_luma8();
+    let compressed = image::load_from_memory(compressed).ok()?.to_luma8();
+    if original.dimensions() != compressed.dimensions() {
+        return None;
+    }
+    Some(ssim(&original, &compressed))
+}
+
+/// Mean SSIM over 8x8 windows (smaller at the right and bottom edges), with the usual
+/// constants for 8-bit samples.
+fn ssim(a: &image::GrayImage, b: &image::GrayImage) -> f64 {
+    const WINDOW: u32 = 8;
+    const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+    let (width, height) = a.dimensions();
+    let mut total = 0.0;
+    let mut windows = 0;
+    for y0 in (0..height).step_by(WINDOW as usize) {
+        for x0 in (0..width).step_by(WINDOW as usize) {
+            let (mut sum_a, mut sum_b, mut sum_aa, mut sum_bb, mut sum_ab) = (0.0, 0.0, 0.0, 0.0, 0.0);
+            let mut n = 0.0;
+            for y in y0..(y0 + WINDOW).min(height) {
+                for x in x0..(x0 + WINDOW).min(width) {
+                    let pa = a.get_pixel(x, y)[0] as f64;
+                    let pb = b.get_pixel(x, y)[0] as f64;
+                    sum_a += pa;
+                    sum_b += pb;
+                    sum_aa += pa * pa;
+                    sum_bb += pb * pb;
+                    sum_ab += pa * pb;
+                    n += 1.0;
+                }
+            }
+            let (mean_a, mean_b) = (sum_a / n, sum_b / n);
+            let var_a = sum_aa / n - mean_a * mean_a;
+            let var_b = sum_bb / n - mean_b * mean_b;
+            let covariance = sum_ab / n - mean_a * mean_b;
+            total += ((2.0 * mean_a * mean_b + C1) * (2.0 * covariance + C2))
+                / ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2));
+            windows += 1;
+        }
+    }
+    if windows == 0 {
+        1.0
+    } else {
+        total / windows as f64
+    }
+}
+
+/// Count distinct RGBA colors, stopping as soon as `max_count` is reached.
+/// Much cheaper than a full imagequant run, so it is used to decide whether quantization is needed.
+fn estimate_unique_colors(img: &image::RgbaImage, max_count: usize) -> usize {
+    let mut colors: HashSet<[u8; 4]> = HashSet::new();
+    for px in img.pixels() {
+        if colors.len() >= max_count {
+            break;
+        }
+        colors.insert(px.0);
+    }
+    colors.len()
+}
+
+fn format_name(format: image::ImageFormat) -> &'static str {
+    format.extensions_str().first().copied().unwrap_or("unknown")
+}
+
+fn main() {
+    // A broken profile shouldn't keep the app from starting, it runs on the defaults instead
+    match CompressionOptions::from_env() {
+        Ok(options) => {
+            let _ = BASE_OPTIONS.set(options);
+        }
+        Err(e) => println!("[Options] {} ignored: {}", PROFILE_ENV, e),
+    }
     tauri::Builder::default()
         .plugin(tauri_plugin_shell::init())
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_fs::init())
-        .invoke_handler(tauri::generate_handler![scan_paths, compress_files])
+        .manage(CancellationToken::default())
+        .manage(DirectoryWatchers::default())
+        .invoke_handler(tauri::generate_handler![
+            scan_paths,
+            scan_paths_with_options,
+            scan_paths_by_dimension,
+            scan_paths_with_meta,
+            scan_paths_filtered,
+            scan_paths_respectful,
+            scan_paths_streaming,
+            build_options,
+            load_config,
+            save_config,
+            analyze_color_count,
+            analyze_image,
+            inspect_png_chunks,
+            get_file_info,
+            preview_oxipng_options,
+            compute_batch_savings_estimate,
+            get_compression_estimate,
+            compare_compression_modes,
+            find_optimal_png_quality,
+            compare_files,
+            validate_options,
+            compress_files,
+            compress_files_mapped,
+            compress_files_blocking,
+            cancel_compression,
+            watch_directory,
+            unwatch_directory,
+            restore_backup,
+            restore_backups_in_dir,
+            compress_to_buffer,
+            compress_data,
+            validate_output,
+            validate_batch,
+            validate_files,
+            export_results_csv,
+            compress_clipboard_image
+        ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An RGB gradient, distinct enough per pixel that compression has work to do.
+    fn gradient(width: u32, height: u32) -> image::RgbImage {
+        image::RgbImage::from_fn(width, height, |x, y| image::Rgb([(x * 4) as u8, (y * 4) as u8, ((x + y) * 2) as u8]))
+    }
+
+    fn encode(img: &image::DynamicImage, format: image::ImageOutputFormat) -> Vec<u8> {
+        let mut data = Vec::new();
+        img.write_to(&mut std::io::Cursor::new(&mut data), format).unwrap();
+        data
+    }
+
+    fn png_bytes(img: image::RgbImage) -> Vec<u8> {
+        encode(&image::DynamicImage::ImageRgb8(img), image::ImageOutputFormat::Png)
+    }
+
+    #[test]
+    fn detect_format_reads_magic_bytes_not_extension() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("photo.jpg");
+        fs::write(&path, png_bytes(gradient(32, 32))).unwrap();
+        assert_eq!(detect_format(&path), Some(image::ImageFormat::Png));
+    }
+
+    #[test]
+    fn png_with_jpg_extension_is_compressed_as_png() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("photo.jpg");
+        fs::write(&path, png_bytes(gradient(64, 64))).unwrap();
+
+        let result = process_single_file(&path, &CompressionOptions::default());
+        assert_eq!(result.status, "success", "{:?}", result.error);
+        assert!(result.notes.contains(&"format_mismatch: extension=jpg, actual=png".to_string()));
+        assert_eq!(result.output_format.as_deref(), Some("png"));
+        let written = fs::read(&path).unwrap();
+        assert_eq!(image::guess_format(&written).unwrap(), image::ImageFormat::Png);
+        assert_eq!(image::load_from_memory(&written).unwrap().to_rgb8(), gradient(64, 64));
+    }
+
+    #[test]
+    fn png_with_jpg_extension_converts_to_target_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("photo.jpg");
+        fs::write(&path, png_bytes(gradient(64, 64))).unwrap();
+
+        let options = CompressionOptions { output_format: Some("webp".to_string()), ..Default::default() };
+        let result = process_single_file(&path, &options);
+        assert_eq!(result.status, "success", "{:?}", result.error);
+        assert_eq!(result.output_format.as_deref(), Some("webp"));
+        let written = fs::read(&result.file_path).unwrap();
+        assert_eq!(image::guess_format(&written).unwrap(), image::ImageFormat::WebP);
+    }
+
+    #[test]
+    fn estimate_unique_colors_stops_at_max_count() {
+        let img = image::DynamicImage::ImageRgb8(gradient(64, 64)).to_rgba8();
+        let exact: HashSet<[u8; 4]> = img.pixels().map(|px| px.0).collect();
+        assert_eq!(estimate_unique_colors(&img, usize::MAX), exact.len());
+        assert_eq!(estimate_unique_colors(&img, 256), 256);
+
+        let flat = image::RgbaImage::from_pixel(64, 64, image::Rgba([10, 20, 30, 255]));
+        assert_eq!(estimate_unique_colors(&flat, 256), 1);
+    }
+
+    /// Timing comparison for the auto mode pre-check, run with `cargo test -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_estimate_unique_colors_vs_imagequant() {
+        let img = image::DynamicImage::ImageRgb8(gradient(1024, 1024));
+        let rgba = img.to_rgba8();
+        let options = CompressionOptions::default();
+
+        let start = std::time::Instant::now();
+        let colors = estimate_unique_colors(&rgba, 257);
+        let estimate = start.elapsed();
+
+        let start = std::time::Instant::now();
+        quantize_to_png(&img, 0, options.quality, &options, None).unwrap();
+        let quantize = start.elapsed();
+
+        println!("estimate_unique_colors: {:?} ({} colors), imagequant: {:?}", estimate, colors, quantize);
+        assert!(estimate < quantize);
+    }
+
+    /// A PNG plus a symlink to it, so the strategies can be checked against an untouched target.
+    #[cfg(unix)]
+    fn png_symlink(dir: &Path) -> (PathBuf, PathBuf, Vec<u8>) {
+        let target = dir.join("target.png");
+        let link = dir.join("link.png");
+        let data = png_bytes(gradient(64, 64));
+        fs::write(&target, &data).unwrap();
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+        (target, link, data)
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_strategy_follow_compresses_the_target() {
+        let dir = tempfile::tempdir().unwrap();
+        let (target, link, data) = png_symlink(dir.path());
+
+        let options = CompressionOptions { symlink_strategy: "follow".to_string(), ..Default::default() };
+        let result = process_single_file(&link, &options);
+        assert_eq!(result.status, "success", "{:?}", result.error);
+        assert!(result.notes.contains(&format!("is_symlink_to: {}", target.display())));
+        assert!(fs::read(&target).unwrap().len() < data.len());
+        assert!(fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_strategy_skip_leaves_the_target_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let (target, link, data) = png_symlink(dir.path());
+
+        let result = process_single_file(&link, &CompressionOptions::default());
+        assert_eq!(result.status, "skipped_symlink");
+        assert_eq!(fs::read(&target).unwrap(), data);
+        assert!(fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_strategy_error_reports_the_link() {
+        let dir = tempfile::tempdir().unwrap();
+        let (target, link, data) = png_symlink(dir.path());
+
+        let options = CompressionOptions { symlink_strategy: "error".to_string(), ..Default::default() };
+        let result = process_single_file(&link, &options);
+        assert_eq!(result.status, "error");
+        assert_eq!(result.error.as_deref(), Some("Source is a symlink"));
+        assert_eq!(fs::read(&target).unwrap(), data);
+    }
+
+    /// `count` identical gradient PNGs in `dir`, returned with their encoded bytes.
+    fn png_files(dir: &Path, count: usize) -> (Vec<PathBuf>, Vec<u8>) {
+        let data = png_bytes(gradient(64, 64));
+        let paths = (0..count)
+            .map(|i| {
+                let path = dir.join(format!("{}.png", i));
+                fs::write(&path, &data).unwrap();
+                path
+            })
+            .collect();
+        (paths, data)
+    }
+
+    #[test]
+    fn process_batch_only_processes_consumed_items() {
+        let dir = tempfile::tempdir().unwrap();
+        let (paths, data) = png_files(dir.path(), 3);
+
+        let mut results = FileProcessor::new(CompressionOptions::default()).process_batch(paths.clone().into_iter());
+        let first = results.next().unwrap();
+        assert_eq!(first.status, "success", "{:?}", first.error);
+        assert_eq!(first.file_path, paths[0].to_string_lossy());
+        assert!(fs::read(&paths[0]).unwrap().len() < data.len());
+        assert_eq!(fs::read(&paths[1]).unwrap(), data);
+        assert_eq!(fs::read(&paths[2]).unwrap(), data);
+
+        drop(results);
+        assert_eq!(fs::read(&paths[1]).unwrap(), data);
+    }
+
+    #[test]
+    fn process_batch_parallel_keeps_input_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let (paths, _) = png_files(dir.path(), 4);
+
+        let results = FileProcessor::new(CompressionOptions::default()).process_batch_parallel(paths.clone(), 2);
+        let result_paths: Vec<String> = results.iter().map(|r| r.file_path.clone()).collect();
+        let expected: Vec<String> = paths.iter().map(|p| p.to_string_lossy().to_string()).collect();
+        assert_eq!(result_paths, expected);
+        assert!(results.iter().all(|r| r.status == "success"));
+    }
+
+    #[test]
+    fn strip_unsafe_chunks_only_keeps_just_the_safe_set() {
+        let mut png = img_parts::png::Png::from_bytes(png_bytes(gradient(64, 64)).into()).unwrap();
+        let extra: [(&Bytes4, &[u8]); 4] = [
+            (b"tEXt", b"Title\0logo"),
+            (b"zTXt", b"Comment\0\0x"),
+            (b"tIME", &[7, 234, 1, 1, 0, 0, 0]),
+            (b"prVt", b"tool state"),
+        ];
+        for (name, contents) in extra {
+            png.chunks_mut().insert(1, img_parts::png::PngChunk::new(*name, contents.to_vec().into()));
+        }
+        let mut input = Vec::new();
+        png.encoder().write_to(&mut input).unwrap();
+
+        let options = CompressionOptions {
+            mode: "lossless".to_string(),
+            png_strip_unsafe_chunks_only: true,
+            ..Default::default()
+        };
+        let output = compress_bytes(&input, image::ImageFormat::Png, &options, &mut Vec::new()).unwrap().0;
+        let chunks = png_chunk_names(&output);
+        assert!(chunks.contains(b"tEXt"));
+        for stripped in [b"zTXt", b"tIME", b"prVt"] {
+            assert!(!chunks.contains(stripped), "{} kept", String::from_utf8_lossy(stripped));
+        }
+        assert!(chunks.iter().all(|name| safe_chunk_names().contains(name)));
+    }
+
+    #[test]
+    fn sha256_hex_matches_sha256sum() {
+        // `printf abc | sha256sum`
+        assert_eq!(sha256_hex(b"abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    }
+
+    #[test]
+    fn original_file_hash_is_taken_before_compression() {
+        let dir = tempfile::tempdir().unwrap();
+        let (paths, data) = png_files(dir.path(), 1);
+
+        let options = CompressionOptions { hash_originals: true, ..Default::default() };
+        let result = process_single_file(&paths[0], &options);
+        assert_eq!(result.status, "success", "{:?}", result.error);
+        assert_ne!(fs::read(&paths[0]).unwrap(), data);
+        assert_eq!(result.original_file_hash, Some(sha256_hex(&data)));
+
+        let result = process_single_file(&paths[0], &CompressionOptions::default());
+        assert_eq!(result.original_file_hash, None);
+    }
+
+    #[test]
+    #[serial_test::serial]
+    fn from_env_loads_the_named_preset() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join("test_profile.preset.json"),
+            r#"{ "quality": 60, "mode": "lossless", "output_format": "webp" }"#,
+        )
+        .unwrap();
+        std::env::set_var(PRESETS_DIR_ENV, dir.path());
+        std::env::set_var(PROFILE_ENV, "test_profile");
+        let options = CompressionOptions::from_env();
+        std::env::remove_var(PROFILE_ENV);
+        std::env::remove_var(PRESETS_DIR_ENV);
+
+        let options = options.unwrap();
+        assert_eq!(options.quality, 60);
+        assert_eq!(options.mode, "lossless");
+        assert_eq!(options.output_format.as_deref(), Some("webp"));
+        // Keys the preset leaves out keep their defaults
+        assert_eq!(options.symlink_strategy, CompressionOptions::default().symlink_strategy);
+    }
+
+    #[test]
+    #[serial_test::serial]
+    fn from_env_rejects_preset_names_outside_the_presets_dir() {
+        std::env::set_var(PROFILE_ENV, "../test_profile");
+        let options = CompressionOptions::from_env();
+        std::env::remove_var(PROFILE_ENV);
+        assert!(matches!(options, Err(CompressionError::Validation(_))));
+
+        assert_eq!(CompressionOptions::from_env().unwrap().quality, CompressionOptions::default().quality);
+    }
+
+    fn option_values(json: serde_json::Value) -> HashMap<String, serde_json::Value> {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn options_from_map_apply_known_keys() {
+        let values = option_values(serde_json::json!({ "quality": 55, "output_format": "webp", "dry_run": true }));
+        let converted = ConvertedOptions::try_from(values).unwrap();
+        assert!(converted.warnings.is_empty());
+        assert_eq!(converted.options.quality, 55);
+        assert_eq!(converted.options.output_format.as_deref(), Some("webp"));
+        assert!(converted.options.dry_run);
+        assert_eq!(converted.options.mode, CompressionOptions::default().mode);
+    }
+
+    #[test]
+    fn options_from_map_report_every_type_mismatch() {
+        let values = option_values(serde_json::json!({ "quality": "high", "dry_run": 1, "bogus": true }));
+        let errors = CompressionOptions::try_from(values).unwrap_err();
+        let keys: Vec<(&str, ConversionSeverity)> = errors.iter().map(|e| (e.key.as_str(), e.severity)).collect();
+        assert_eq!(
+            keys,
+            [
+                ("bogus", ConversionSeverity::Warning),
+                ("dry_run", ConversionSeverity::Error),
+                ("quality", ConversionSeverity::Error),
+            ]
+        );
+    }
+
+    #[test]
+    fn options_from_map_warn_about_unknown_keys() {
+        let values = option_values(serde_json::json!({ "quality": 70, "qualty": 80 }));
+        let converted = ConvertedOptions::try_from(values).unwrap();
+        assert_eq!(converted.options.quality, 70);
+        assert_eq!(converted.warnings.len(), 1);
+        assert_eq!(converted.warnings[0].key, "qualty");
+        assert_eq!(converted.warnings[0].severity, ConversionSeverity::Warning);
+    }
+
+    /// A write that fails with a transient error `failures` times before it succeeds.
+    fn flaky_write(failures: usize) -> impl FnMut() -> std::io::Result<usize> {
+        let mut calls = 0;
+        move || {
+            calls += 1;
+            if calls <= failures {
+                Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "share unavailable"))
+            } else {
+                Ok(calls)
+            }
+        }
+    }
+
+    #[test]
+    fn retry_io_backs_off_exponentially() {
+        let options = CompressionOptions { max_retries: Some(3), retry_delay_ms: 20, ..Default::default() };
+        let started = std::time::Instant::now();
+        let (result, delays) = retry_io(&options, false, flaky_write(2));
+        assert_eq!(result.unwrap(), 3);
+        assert_eq!(delays, [20, 40]);
+        assert!(started.elapsed() >= std::time::Duration::from_millis(60));
+    }
+
+    #[test]
+    fn backoff_network_only_keeps_a_fixed_delay_off_the_network() {
+        let options = CompressionOptions {
+            max_retries: Some(3),
+            retry_delay_ms: 5,
+            backoff_network_only: true,
+            ..Default::default()
+        };
+        let (result, delays) = retry_io(&options, false, flaky_write(2));
+        assert_eq!(result.unwrap(), 3);
+        assert_eq!(delays, [5, 5]);
+
+        let (result, delays) = retry_io(&options, true, flaky_write(5));
+        assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::TimedOut);
+        assert_eq!(delays, [5, 10, 20]);
+    }
+
+    #[test]
+    fn retry_delay_ms_is_capped_at_eight_times_the_base() {
+        let options = CompressionOptions { retry_delay_ms: 100, ..Default::default() };
+        let delays: Vec<u64> = (0..6).map(|attempt| retry_delay_ms(&options, attempt, false)).collect();
+        assert_eq!(delays, [100, 200, 400, 800, 800, 800]);
+    }
+
+    /// A gradient encoded by mozjpeg with the given `jpeg_subsampling`.
+    fn jpeg_with_subsampling(subsampling: &str) -> Vec<u8> {
+        let options = CompressionOptions { jpeg_subsampling: Some(subsampling.to_string()), ..Default::default() };
+        compress_jpeg_at(&image::DynamicImage::ImageRgb8(gradient(64, 64)), 90, &options).unwrap()
+    }
+
+    #[test]
+    fn jpeg_chroma_subsampling_reads_mozjpeg_output() {
+        for subsampling in ["4:4:4", "4:2:2", "4:2:0"] {
+            assert_eq!(jpeg_chroma_subsampling(&jpeg_with_subsampling(subsampling)).as_deref(), Some(subsampling));
+        }
+        let gray = image::DynamicImage::ImageLuma8(image::DynamicImage::ImageRgb8(gradient(64, 64)).to_luma8());
+        let data = compress_jpeg_at(&gray, 90, &CompressionOptions::default()).unwrap();
+        assert_eq!(jpeg_chroma_subsampling(&data).as_deref(), Some("4:0:0"));
+    }
+
+    #[test]
+    fn subsampling_upgrade_is_reported_in_notes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("photo.jpg");
+        fs::write(&path, jpeg_with_subsampling("4:2:0")).unwrap();
+
+        let options = CompressionOptions {
+            mode: "lossy".to_string(),
+            detect_jpeg_subsampling: true,
+            jpeg_subsampling: Some("4:4:4".to_string()),
+            ..Default::default()
+        };
+        let result = process_single_file(&path, &options);
+        assert_eq!(result.source_chroma_subsampling.as_deref(), Some("4:2:0"));
+        assert!(result.notes.contains(&"subsampling_upgrade_may_increase_size".to_string()));
+
+        let options = CompressionOptions { jpeg_subsampling: Some("4:2:0".to_string()), ..options };
+        let result = process_single_file(&path, &options);
+        assert!(!result.notes.contains(&"subsampling_upgrade_may_increase_size".to_string()));
+    }
+
+    /// A minimal v2 ICC profile holding only a `desc` tag, enough for the detection to name it.
+    fn icc_profile(description: &str) -> Vec<u8> {
+        let mut desc = b"desc\0\0\0\0".to_vec();
+        desc.extend(((description.len() + 1) as u32).to_be_bytes());
+        desc.extend(description.as_bytes());
+        desc.push(0);
+
+        let mut profile = vec![0u8; 128];
+        profile[12..16].copy_from_slice(b"mntr");
+        profile[16..20].copy_from_slice(b"RGB ");
+        profile[36..40].copy_from_slice(b"acsp");
+        profile.extend(1u32.to_be_bytes());
+        profile.extend(b"desc");
+        profile.extend(144u32.to_be_bytes());
+        profile.extend((desc.len() as u32).to_be_bytes());
+        profile.extend(desc);
+        let size = profile.len() as u32;
+        profile[0..4].copy_from_slice(&size.to_be_bytes());
+        profile
+    }
+
+    #[test]
+    fn analyze_image_detects_an_adobe_rgb_png_profile() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wide.png");
+        let profile = icc_profile("Adobe RGB (1998)");
+        fs::write(&path, png_with_icc(&profile)).unwrap();
+
+        let analysis = tauri::async_runtime::block_on(analyze_image(path.to_string_lossy().to_string())).unwrap();
+        assert!(analysis.has_icc_profile);
+        assert_eq!(analysis.icc_profile_name.as_deref(), Some("Adobe RGB (1998)"));
+        assert_eq!(analysis.icc_profile_size_bytes, profile.len() as u32);
+    }
+
+    #[test]
+    fn analyze_image_reports_no_profile_for_a_plain_png() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("plain.png");
+        fs::write(&path, png_bytes(gradient(16, 16))).unwrap();
+
+        let analysis = tauri::async_runtime::block_on(analyze_image(path.to_string_lossy().to_string())).unwrap();
+        assert!(!analysis.has_icc_profile);
+        assert_eq!(analysis.icc_profile_name, None);
+        assert_eq!(analysis.icc_profile_size_bytes, 0);
+    }
+
+    /// One PNG in each of `count` subdirectories of `root`.
+    fn nested_pngs(root: &Path, count: usize) -> Vec<String> {
+        let data = png_bytes(gradient(32, 32));
+        (0..count)
+            .map(|i| {
+                let dir = root.join(format!("album{}", i));
+                fs::create_dir_all(&dir).unwrap();
+                let path = dir.join("photo.png");
+                fs::write(&path, &data).unwrap();
+                path.to_string_lossy().to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn pre_create_output_dirs_creates_every_mirrored_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = nested_pngs(&dir.path().join("src"), 10);
+        let output_dir = dir.path().join("out");
+
+        pre_create_output_dirs(&paths, &output_dir.to_string_lossy()).unwrap();
+        for i in 0..10 {
+            let mirrored = output_dir.join(format!("album{}", i));
+            assert!(mirrored.is_dir(), "{} missing", mirrored.display());
+            assert_eq!(fs::read_dir(&mirrored).unwrap().count(), 0);
+        }
+    }
+
+    #[test]
+    fn mirrored_batch_writes_into_the_pre_created_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = nested_pngs(&dir.path().join("src"), 10);
+        let output_dir = dir.path().join("out");
+
+        let mut options = CompressionOptions {
+            output_dir: Some(output_dir.to_string_lossy().to_string()),
+            mirror_directory_structure: true,
+            ..Default::default()
+        };
+        prepare_mirrored_output(&paths, &mut options).unwrap();
+        let results = FileProcessor::new(options).process_batch_parallel(paths.iter().map(PathBuf::from).collect(), 4);
+        for (i, result) in results.iter().enumerate() {
+            assert_eq!(result.status, "success", "{:?}", result.error);
+            let expected = output_dir.join(format!("album{}", i)).join("photo.png");
+            assert_eq!(result.file_path, expected.to_string_lossy());
+            assert!(expected.is_file());
+        }
+    }
+
+    #[test]
+    fn pre_create_output_dirs_fails_when_a_directory_cannot_be_created() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = nested_pngs(&dir.path().join("src"), 3);
+        let output_dir = dir.path().join("out");
+        fs::write(&output_dir, b"not a directory").unwrap();
+
+        let error = pre_create_output_dirs(&paths, &output_dir.to_string_lossy()).unwrap_err();
+        assert!(error.starts_with("Failed to create output directory"), "{}", error);
+    }
+
+    #[test]
+    fn batch_savings_estimate_is_close_to_the_real_savings() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths: Vec<String> = (0..50u32)
+            .map(|i| {
+                let path = dir.path().join(format!("{}.png", i));
+                fs::write(&path, png_bytes(gradient(16 + i * 2, 16 + i))).unwrap();
+                path.to_string_lossy().to_string()
+            })
+            .collect();
+        let options = CompressionOptions { mode: "lossless".to_string(), ..Default::default() };
+
+        let true_savings: u64 = paths
+            .iter()
+            .map(|p| {
+                let size = fs::metadata(p).unwrap().len();
+                size.saturating_sub(compress_path_to_memory(Path::new(p), &options).unwrap().len() as u64)
+            })
+            .sum();
+        let estimate = tauri::async_runtime::block_on(compute_batch_savings_estimate(paths, options)).unwrap();
+
+        assert_eq!(estimate.sample_size, 20);
+        let error = (estimate.estimated_total_savings_bytes as f64 - true_savings as f64).abs() / true_savings as f64;
+        assert!(error < 0.2, "estimated {} bytes, saved {}", estimate.estimated_total_savings_bytes, true_savings);
+    }
+
+    /// A folder holding a thumbnail, a full HD image, a wide banner and a PNG that can't be read.
+    fn sized_images(dir: &Path) -> String {
+        for (name, width, height) in [("thumb", 100, 50), ("full", 2000, 1200), ("banner", 3000, 500)] {
+            let img = image::RgbImage::new(width, height);
+            fs::write(dir.join(format!("{}.png", name)), png_bytes(img)).unwrap();
+        }
+        fs::write(dir.join("broken.png"), b"not a png").unwrap();
+        dir.to_string_lossy().to_string()
+    }
+
+    fn file_name(path: &str) -> &str {
+        Path::new(path).file_name().unwrap().to_str().unwrap()
+    }
+
+    #[test]
+    fn scan_filters_by_dimensions() {
+        let dir = tempfile::tempdir().unwrap();
+        let folder = sized_images(dir.path());
+
+        let options = ScanOptions { min_width: Some(1920), min_height: Some(1080), ..Default::default() };
+        let result = tauri::async_runtime::block_on(scan_paths_with_options(vec![folder.clone()], options));
+        let mut found: Vec<(&str, Option<u32>, Option<u32>)> =
+            result.files.iter().map(|f| (file_name(&f.path), f.width, f.height)).collect();
+        found.sort();
+        assert_eq!(found, [("broken.png", None, None), ("full.png", Some(2000), Some(1200))]);
+        assert_eq!(result.excluded_by_dimension, 2);
+
+        let options = ScanOptions { max_width: Some(2000), max_height: Some(600), ..Default::default() };
+        let result = tauri::async_runtime::block_on(scan_paths_with_options(vec![folder], options));
+        let mut found: Vec<&str> = result.files.iter().map(|f| file_name(&f.path)).collect();
+        found.sort();
+        assert_eq!(found, ["broken.png", "thumb.png"]);
+        assert_eq!(result.excluded_by_dimension, 2);
+    }
+
+    #[test]
+    fn scan_keeps_unreadable_files_with_a_warning() {
+        let dir = tempfile::tempdir().unwrap();
+        let folder = sized_images(dir.path());
+
+        let result = tauri::async_runtime::block_on(scan_paths_with_options(vec![folder.clone()], ScanOptions::default()));
+        assert_eq!(result.files.len(), 4);
+        let broken = result.files.iter().find(|f| file_name(&f.path) == "broken.png").unwrap();
+        assert!(broken.warning.as_deref().unwrap().starts_with("Failed to read dimensions"));
+        assert!(result.files.iter().filter(|f| f.path != broken.path).all(|f| f.warning.is_none()));
+
+        let mut found = tauri::async_runtime::block_on(scan_paths_by_dimension(vec![folder], Some(1920), None));
+        found.sort();
+        let found: Vec<&str> = found.iter().map(|p| file_name(p)).collect();
+        assert_eq!(found, ["banner.png", "broken.png", "full.png"]);
+    }
+
+    #[test]
+    fn compress_to_buffer_returns_decodable_png_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("photo.png");
+        let data = png_bytes(gradient(100, 100));
+        fs::write(&path, &data).unwrap();
+        let path = path.to_string_lossy().to_string();
+
+        let encoded =
+            tauri::async_runtime::block_on(compress_to_buffer(path.clone(), CompressionOptions::default(), None)).unwrap();
+        let bytes = base64::engine::general_purpose::STANDARD.decode(encoded).unwrap();
+        let img = image::load_from_memory(&bytes).unwrap();
+        assert_eq!(image::guess_format(&bytes).unwrap(), image::ImageFormat::Png);
+        assert_eq!((img.width(), img.height()), (100, 100));
+        // Nothing is written back
+        assert_eq!(fs::read(&path).unwrap(), data);
+
+        let error =
+            tauri::async_runtime::block_on(compress_to_buffer(path, CompressionOptions::default(), Some(10))).unwrap_err();
+        assert!(matches!(error, CompressionError::Validation(_)));
+    }
+
+    #[test]
+    fn luma_weights_keep_more_structure_than_default_weights() {
+        let img = image::DynamicImage::ImageRgb8(gradient(128, 128));
+        let original = encode(&img, image::ImageOutputFormat::Png);
+        let score = |imagequant_weights| {
+            let options = CompressionOptions {
+                imagequant_weights,
+                palette_size: Some(32),
+                dithering: Some(0.0),
+                ..Default::default()
+            };
+            quality_score(&original, &quantize_to_png(&img, 0, 100, &options, None).unwrap()).unwrap()
+        };
+
+        let default = score(None);
+        let luma = score(Some([0.299, 0.587, 0.114, 1.0]));
+        assert!(luma > default, "luma weighted SSIM {:.4}, default {:.4}", luma, default);
+    }
+
+    #[test]
+    fn imagequant_weights_are_recorded_in_notes() {
+        let dir = tempfile::tempdir().unwrap();
+        let (paths, _) = png_files(dir.path(), 1);
+
+        let options = CompressionOptions {
+            mode: "lossy".to_string(),
+            imagequant_weights: Some([0.5, 1.0, 0.25, 1.0]),
+            ..Default::default()
+        };
+        let result = process_single_file(&paths[0], &options);
+        assert!(result.notes.contains(&"imagequant_weights: [0.5, 1.0, 0.25, 1.0]".to_string()), "{:?}", result.notes);
+    }
+
+    /// A gradient PNG set to mode 444, with its encoded bytes.
+    #[cfg(unix)]
+    fn readonly_png(dir: &Path) -> (PathBuf, Vec<u8>) {
+        use std::os::unix::fs::PermissionsExt;
+        let (paths, data) = png_files(dir, 1);
+        fs::set_permissions(&paths[0], fs::Permissions::from_mode(0o444)).unwrap();
+        (paths[0].clone(), data)
+    }
+
+    #[cfg(unix)]
+    fn readonly_options(strategy: &str) -> CompressionOptions {
+        CompressionOptions { readonly_strategy: strategy.to_string(), ..Default::default() }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn readonly_strategy_skip_leaves_the_file_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let (path, data) = readonly_png(dir.path());
+
+        let result = process_single_file(&path, &readonly_options("skip"));
+        assert_eq!(result.status, "skipped_readonly");
+        assert_eq!(fs::read(&path).unwrap(), data);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn readonly_strategy_error_fails_before_writing() {
+        let dir = tempfile::tempdir().unwrap();
+        let (path, data) = readonly_png(dir.path());
+
+        let result = process_single_file(&path, &readonly_options("error"));
+        assert_eq!(result.status, "error");
+        assert_eq!(result.error.as_deref(), Some("Source is read-only"));
+        assert_eq!(fs::read(&path).unwrap(), data);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn readonly_strategy_copy_then_compress_keeps_the_file_read_only() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let (path, data) = readonly_png(dir.path());
+
+        let result = process_single_file(&path, &readonly_options("copy_then_compress"));
+        assert_eq!(result.status, "success", "{:?}", result.error);
+        assert!(result.notes.contains(&"readonly: replaced via temp copy".to_string()));
+        assert!(fs::read(&path).unwrap().len() < data.len());
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o444);
+    }
+
+    /// Compress one gradient PNG under `mode`, returning the result and the file size before and after.
+    fn benchmark_run(mode: BenchmarkMode) -> (CompressionResult, u64, u64) {
+        let dir = tempfile::tempdir().unwrap();
+        let (paths, data) = png_files(dir.path(), 1);
+        let result = process_single_file(&paths[0], &CompressionOptions { benchmark_mode: mode, ..Default::default() });
+        assert_eq!(result.status, "success", "{:?}", result.error);
+        (result, data.len() as u64, fs::metadata(&paths[0]).unwrap().len())
+    }
+
+    #[test]
+    fn benchmark_mode_off_writes_without_timing() {
+        let (result, original, after) = benchmark_run(BenchmarkMode::Off);
+        assert_eq!(result.compression_time_ms, None);
+        assert!(after < original);
+        assert_eq!(result.compressed_size, after);
+    }
+
+    #[test]
+    fn benchmark_mode_timing_only_reports_no_sizes() {
+        let (result, original, after) = benchmark_run(BenchmarkMode::TimingOnly);
+        assert!(result.compression_time_ms.is_some());
+        assert_eq!(after, original);
+        assert_eq!((result.compressed_size, result.saved_before), (original, 0));
+        assert!(result.notes.contains(&"benchmark: output not written".to_string()));
+    }
+
+    #[test]
+    fn benchmark_mode_full_reports_sizes_without_writing() {
+        let (result, original, after) = benchmark_run(BenchmarkMode::Full);
+        assert!(result.compression_time_ms.is_some());
+        assert_eq!(after, original);
+        assert!(result.compressed_size < original);
+        assert_eq!(result.saved_before, original - result.compressed_size);
+    }
+
+    #[test]
+    fn benchmark_mode_with_write_times_a_normal_run() {
+        let (result, original, after) = benchmark_run(BenchmarkMode::WithWrite);
+        assert!(result.compression_time_ms.is_some());
+        assert!(after < original);
+        assert_eq!(result.compressed_size, after);
+    }
+
+    #[test]
+    fn benchmark_report_leaves_out_sizes_for_timing_only() {
+        let (result, _, _) = benchmark_run(BenchmarkMode::TimingOnly);
+        let report = BenchmarkReport::from_results(BenchmarkMode::TimingOnly, &[result], std::time::Duration::from_secs(1));
+        assert_eq!(report.file_count, 1);
+        assert_eq!(report.compressed_bytes, None);
+
+        let (result, _, after) = benchmark_run(BenchmarkMode::WithWrite);
+        let report = BenchmarkReport::from_results(BenchmarkMode::WithWrite, &[result], std::time::Duration::from_secs(1));
+        assert_eq!(report.compressed_bytes, Some(after));
+        assert_eq!(serde_json::to_value(report.mode).unwrap(), "with_write");
+    }
+
+    #[test]
+    fn streaming_scan_delivers_every_file_in_walk_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = png_bytes(gradient(2, 2));
+        for i in 0..500 {
+            fs::write(dir.path().join(format!("{:03}.png", i)), &data).unwrap();
+        }
+        let paths = vec![dir.path().to_string_lossy().to_string()];
+        let mut walked = Vec::new();
+        walk_image_paths(&paths, None, false, |path| walked.push(path));
+
+        // A buffer far smaller than the batch, with a consumer slower than the walk
+        let options = ScanOptions { scan_buffer_size: 8, ..Default::default() };
+        let mut received = Vec::new();
+        let count = tauri::async_runtime::block_on(stream_scanned_files(paths, options, |file| {
+            if received.len() % 50 == 0 {
+                std::thread::sleep(std::time::Duration::from_millis(5));
+            }
+            received.push(file);
+        }))
+        .unwrap();
+
+        assert_eq!(count, 500);
+        let received: Vec<String> = received.into_iter().map(|file| file.path).collect();
+        assert_eq!(received, walked);
+    }
+
+    #[test]
+    fn quality_too_low_retries_at_a_lower_quality() {
+        // Two colors can't reach quality 50-80 on a gradient, but anything goes from 0
+        let img = image::DynamicImage::ImageRgb8(gradient(64, 64));
+        let input = encode(&img, image::ImageOutputFormat::Png);
+        let options = CompressionOptions {
+            quality: 80,
+            palette_size: Some(2),
+            retry_on_encode_error_with_lower_quality: true,
+            ..Default::default()
+        };
+        assert!(matches!(quantize_and_optimize(&img, &input, 50, 80, &options), Err(CompressionError::QualityTooLow)));
+
+        let mut notes = Vec::new();
+        let output = compress_png_lossy(&img, &input, &options, &mut notes).unwrap();
+        let retries: Vec<&String> = notes.iter().filter(|n| n.starts_with("lower_quality_retry")).collect();
+        assert_eq!(retries, [&format!("lower_quality_retry: 0-60 -> {} bytes", output.len())]);
+        let decoder = png::Decoder::new(output.as_slice()).read_info().unwrap();
+        assert_eq!(decoder.info().color_type, png::ColorType::Indexed);
+    }
+
+    #[test]
+    fn png_effort_6_is_no_larger_than_effort_2() {
+        let input = png_bytes(gradient(128, 128));
+        let compress = |effort| {
+            let options = CompressionOptions { mode: "lossless".to_string(), png_effort: Some(effort), ..Default::default() };
+            compress_png_lossless(&input, &options).unwrap()
+        };
+        let (effort_2, effort_6) = (compress(2), compress(6));
+        assert!(effort_6.len() <= effort_2.len(), "preset 6: {} bytes, preset 2: {}", effort_6.len(), effort_2.len());
+        assert_eq!(image::load_from_memory(&effort_6).unwrap().to_rgb8(), gradient(128, 128));
+    }
+
+    #[test]
+    fn png_effort_outside_1_to_6_is_rejected() {
+        for effort in [0, 7] {
+            let options = CompressionOptions { png_effort: Some(effort), ..Default::default() };
+            let Err(CompressionError::Validation(errors)) = options.validate() else {
+                panic!("png_effort {} accepted", effort);
+            };
+            assert_eq!(errors, [format!("png_effort {} out of range 1-6", effort)]);
+        }
+    }
+
+    /// A gradient PNG with an iCCP chunk holding `profile`.
+    fn png_with_icc(profile: &[u8]) -> Vec<u8> {
+        let mut png = img_parts::png::Png::from_bytes(png_bytes(gradient(64, 64)).into()).unwrap();
+        png.set_icc_profile(Some(profile.to_vec().into()));
+        let mut data = Vec::new();
+        png.encoder().write_to(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn strip_mode_decides_whether_iccp_is_kept() {
+        let profile = icc_profile("Adobe RGB (1998)");
+        let input = png_with_icc(&profile);
+        for mode in ["lossless", "lossy"] {
+            for (strip_mode, kept) in [("none", true), ("safe", true), ("all", false)] {
+                let options = CompressionOptions {
+                    mode: mode.to_string(),
+                    strip_mode: Some(strip_mode.to_string()),
+                    ..Default::default()
+                };
+                let output = compress_bytes(&input, image::ImageFormat::Png, &options, &mut Vec::new()).unwrap().0;
+                let icc = read_icc_profile(&output, image::ImageFormat::Png);
+                assert_eq!(icc.is_some(), kept, "{} mode, strip_mode {}", mode, strip_mode);
+                if kept {
+                    assert_eq!(icc.unwrap(), profile);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn in_place_webp_conversion_keeps_the_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("photo.png");
+        let data = png_bytes(gradient(64, 64));
+        fs::write(&path, &data).unwrap();
+
+        let options = CompressionOptions { output_format: Some("webp".to_string()), ..Default::default() };
+        let result = process_single_file(&path, &options);
+        assert_eq!(result.status, "success", "{:?}", result.error);
+        assert_eq!(Path::new(&result.file_path), dir.path().join("photo.webp"));
+        assert_eq!(fs::read(&path).unwrap(), data);
+    }
+
+    #[test]
+    fn backup_keeps_the_first_original_across_runs() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("photo.png");
+        let data = png_bytes(gradient(64, 64));
+        fs::write(&path, &data).unwrap();
+
+        // The second run has to write again, so it quantizes harder
+        for quality in [90, 20] {
+            let options = CompressionOptions { mode: "lossy".to_string(), quality, backup: true, ..Default::default() };
+            let result = process_single_file(&path, &options);
+            assert_eq!(result.status, "success", "quality {}: {:?}", quality, result.error);
+            assert!(result.backup_path.is_some());
+        }
+        tauri::async_runtime::block_on(restore_backup(path.to_string_lossy().to_string())).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), data);
+    }
+
+    #[test]
+    fn avif_sources_are_skipped_as_unsupported() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("photo.avif");
+        let png = png_bytes(gradient(16, 16));
+        let data = compress_to_avif(&png, image::ImageFormat::Png, &CompressionOptions::default()).unwrap();
+        fs::write(&path, &data).unwrap();
+
+        let result = process_single_file(&path, &CompressionOptions::default());
+        assert_eq!(result.status, "skipped_unsupported");
+        assert!(result.error.unwrap().contains("AVIF"));
+        assert_eq!(fs::read(&path).unwrap(), data);
+    }
+
+    /// A project folder with images at the top, in `node_modules` and in `vendor`.
+    fn project_tree() -> (tempfile::TempDir, String) {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let data = png_bytes(gradient(2, 2));
+        for file in ["logo.png", "anim.gif", "node_modules/pkg/icon.png", "node_modules/banner.gif", "vendor/lib/badge.png"] {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, &data).unwrap();
+        }
+        let root = root.to_string_lossy().to_string();
+        (dir, root)
+    }
+
+    fn filtered(root: &str, exclude_globs: &[&str]) -> Vec<String> {
+        let exclude_globs = exclude_globs.iter().map(|g| g.to_string()).collect();
+        // Scanned paths are canonicalized
+        let canonical_root = fs::canonicalize(root).unwrap();
+        let mut found: Vec<String> = tauri::async_runtime::block_on(scan_paths_filtered(vec![root.to_string()], exclude_globs))
+            .unwrap()
+            .into_iter()
+            .map(|p| Path::new(&p).strip_prefix(&canonical_root).unwrap().to_string_lossy().replace('\\', "/"))
+            .collect();
+        found.sort();
+        found
+    }
+
+    #[test]
+    fn scan_paths_filtered_handles_overlapping_globs() {
+        let (_dir, root) = project_tree();
+        let found = filtered(&root, &["**/node_modules/**", "**/*.gif", "**/node_modules/pkg/*.png"]);
+        assert_eq!(found, ["logo.png", "vendor/lib/badge.png"]);
+    }
+
+    #[test]
+    fn scan_paths_filtered_follows_platform_case_sensitivity() {
+        let (_dir, root) = project_tree();
+        let found = filtered(&root, &["**/VENDOR/**"]);
+        if cfg!(any(windows, target_os = "macos")) {
+            assert!(!found.contains(&"vendor/lib/badge.png".to_string()));
+        } else {
+            assert!(found.contains(&"vendor/lib/badge.png".to_string()));
+        }
+    }
+
+    #[test]
+    fn scan_paths_filtered_without_globs_matches_scan_paths() {
+        let (_dir, root) = project_tree();
+        // A non-canonical spelling of the root and the root again, both have to be resolved away
+        let paths = vec![format!("{}/./", root), root];
+        let mut unfiltered = tauri::async_runtime::block_on(scan_paths_filtered(paths.clone(), Vec::new())).unwrap();
+        let mut scanned = tauri::async_runtime::block_on(scan_paths(paths, None, None));
+        unfiltered.sort();
+        scanned.sort();
+        assert_eq!(unfiltered.len(), 5);
+        assert_eq!(unfiltered, scanned);
+    }
+
+    #[test]
+    fn scan_paths_filtered_rejects_invalid_globs() {
+        let result = tauri::async_runtime::block_on(scan_paths_filtered(Vec::new(), vec!["***".to_string()]));
+        assert!(matches!(result, Err(CompressionError::Validation(errors)) if errors.len() == 1));
+    }
+
+    /// Whether the JPEG has a `marker` segment.
+    fn has_jpeg_marker(data: &[u8], marker: u8) -> bool {
+        let jpeg = img_parts::jpeg::Jpeg::from_bytes(data.to_vec().into()).unwrap();
+        jpeg.segments().iter().any(|segment| segment.marker() == marker)
+    }
+
+    #[test]
+    fn jpeg_progressive_writes_a_sof2_frame() {
+        let input = jpeg_with_subsampling("4:2:0");
+        let encode = |jpeg_progressive| {
+            let options = CompressionOptions { mode: "lossy".to_string(), jpeg_progressive, ..Default::default() };
+            compress_bytes(&input, image::ImageFormat::Jpeg, &options, &mut Vec::new()).unwrap().0
+        };
+
+        let progressive = encode(true);
+        assert!(has_jpeg_marker(&progressive, 0xC2));
+        assert!(!has_jpeg_marker(&progressive, 0xC0));
+        let baseline = encode(false);
+        assert!(has_jpeg_marker(&baseline, 0xC0));
+        assert!(!has_jpeg_marker(&baseline, 0xC2));
+    }
+
+    #[test]
+    fn palette_size_2_is_smaller_than_256() {
+        // A single color fits either palette, the limit only shows on an image with more colors
+        let img = image::DynamicImage::ImageRgb8(gradient(64, 64));
+        let quantize = |palette_size| {
+            let options = CompressionOptions { palette_size: Some(palette_size), ..Default::default() };
+            quantize_to_png(&img, 0, 100, &options, None).unwrap()
+        };
+        let (two, full) = (quantize(2), quantize(256));
+        assert!(two.len() < full.len(), "palette_size 2: {} bytes, 256: {}", two.len(), full.len());
+        let colors = estimate_unique_colors(&image::load_from_memory(&two).unwrap().to_rgba8(), 257);
+        assert!(colors <= 2);
+    }
+
+    #[test]
+    fn palette_size_below_2_is_rejected() {
+        for palette_size in [0, 1, 257] {
+            let options = CompressionOptions { palette_size: Some(palette_size), ..Default::default() };
+            assert!(options.validate().is_err(), "palette_size {} accepted", palette_size);
+        }
+        let options = CompressionOptions { palette_size: Some(2), ..Default::default() };
+        assert!(options.validate().is_ok());
+    }
+
+    /// The interlace method byte of a PNG's IHDR, right after the 8 byte signature, the chunk
+    /// length and type, and 12 bytes of width, height, depth, color, compression and filter.
+    fn ihdr_interlace(png: &[u8]) -> u8 {
+        assert_eq!(&png[12..16], b"IHDR");
+        png[28]
+    }
+
+    #[test]
+    fn png_interlace_sets_the_ihdr_flag() {
+        let input = png_bytes(gradient(64, 64));
+        let compress = |input: &[u8], png_interlace| {
+            let options = CompressionOptions { mode: "lossless".to_string(), png_interlace, ..Default::default() };
+            compress_png_lossless(input, &options).unwrap()
+        };
+
+        let interlaced = compress(&input, Some(true));
+        assert_eq!(ihdr_interlace(&interlaced), 1);
+        assert_eq!(ihdr_interlace(&compress(&interlaced, Some(false))), 0);
+        // oxipng's default de-interlaces as well
+        assert_eq!(ihdr_interlace(&compress(&interlaced, None)), 0);
+
+        let options = CompressionOptions { mode: "lossy".to_string(), png_interlace: Some(true), ..Default::default() };
+        let lossy = compress_bytes(&input, image::ImageFormat::Png, &options, &mut Vec::new()).unwrap().0;
+        assert_eq!(ihdr_interlace(&lossy), 1);
+    }
+
+    fn png_color_type(png: &[u8]) -> png::ColorType {
+        png::Decoder::new(png).read_info().unwrap().info().color_type
+    }
+
+    #[test]
+    fn grayscale_stored_as_rgb_is_written_as_gray() {
+        let gray = image::RgbImage::from_fn(64, 64, |x, y| {
+            let v = ((x * 3 + y) % 256) as u8;
+            image::Rgb([v, v, v])
+        });
+        let input = png_bytes(gray.clone());
+        assert_eq!(png_color_type(&input), png::ColorType::Rgb);
+
+        let options = CompressionOptions {
+            mode: "lossless".to_string(),
+            allow_grayscale_conversion: true,
+            ..Default::default()
+        };
+        let output = compress_bytes(&input, image::ImageFormat::Png, &options, &mut Vec::new()).unwrap().0;
+        assert_eq!(png_color_type(&output), png::ColorType::Grayscale);
+        assert_eq!(image::load_from_memory(&output).unwrap().to_rgb8(), gray);
+    }
+
+    #[test]
+    fn grayscale_with_transparency_keeps_its_alpha() {
+        let gray = image::RgbaImage::from_fn(64, 64, |x, y| {
+            let v = ((x * 3 + y) % 256) as u8;
+            image::Rgba([v, v, v, (x * 4) as u8])
+        });
+        let input = encode(&image::DynamicImage::ImageRgba8(gray.clone()), image::ImageOutputFormat::Png);
+
+        let options = CompressionOptions {
+            mode: "lossless".to_string(),
+            allow_grayscale_conversion: true,
+            ..Default::default()
+        };
+        let output = compress_bytes(&input, image::ImageFormat::Png, &options, &mut Vec::new()).unwrap().0;
+        assert_eq!(png_color_type(&output), png::ColorType::GrayscaleAlpha);
+        assert_eq!(image::load_from_memory(&output).unwrap().to_rgba8(), gray);
+    }
+
+    /// Compress a mode 604 PNG into a separate output directory, returning the output's mode.
+    #[cfg(unix)]
+    fn output_mode(preserve_permissions: bool) -> u32 {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let (paths, _) = png_files(dir.path(), 1);
+        // Not a mode the usual umasks leave on a new file, so a match comes from the source
+        fs::set_permissions(&paths[0], fs::Permissions::from_mode(0o604)).unwrap();
+
+        let options = CompressionOptions {
+            output_dir: Some(dir.path().join("out").to_string_lossy().to_string()),
+            preserve_permissions,
+            ..Default::default()
+        };
+        let result = process_single_file(&paths[0], &options);
+        assert_eq!(result.status, "success", "{:?}", result.error);
+        fs::metadata(&result.file_path).unwrap().permissions().mode() & 0o777
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn preserve_permissions_copies_the_source_mode() {
+        assert_eq!(output_mode(true), 0o604);
+        assert_ne!(output_mode(false), 0o604);
+    }
+
+    #[test]
+    fn lossless_webp_round_trips_a_solid_color() {
+        let solid = image::RgbaImage::from_pixel(48, 32, image::Rgba([200, 100, 50, 180]));
+        let input = encode(&image::DynamicImage::ImageRgba8(solid.clone()), image::ImageOutputFormat::Png);
+        for webp_method in [None, Some(0), Some(6)] {
+            let options = CompressionOptions { mode: "lossless".to_string(), webp_method, ..Default::default() };
+            let output = compress_to_webp(&input, image::ImageFormat::Png, &options).unwrap();
+            let decoded = webp::Decoder::new(&output).decode().unwrap();
+            assert!(decoded.is_alpha());
+            assert_eq!((decoded.width(), decoded.height()), solid.dimensions());
+            assert_eq!(&*decoded, solid.as_raw().as_slice(), "webp_method {:?}", webp_method);
+        }
+    }
+}
@@ -3,10 +3,12 @@ import { listen, UnlistenFn } from '@tauri-apps/api/event';
 import { open } from '@tauri-apps/plugin-dialog';
 
 export const api = {
-  compressFiles: (paths: string[], options: { mode: 'lossy' | 'lossless', quality: number }) =>
+  compressFiles: (paths: string[], options: { mode: 'lossy' | 'lossless' | 'auto', quality: number }) =>
     invoke('compress_files', { paths, options }),
 
-  onProgress: async (callback: (payload: { done: number, total: number, result: any }) => void): Promise<UnlistenFn> => {
+  cancelCompression: () => invoke('cancel_compression'),
+
+  onProgress: async (callback: (payload: { done: number, total: number, result: any, bytesPerSecond: number }) => void): Promise<UnlistenFn> => {
     return await listen('compression-progress', (event) => {
       callback(event.payload as any);
     });
@@ -22,7 +24,7 @@ export const api = {
     const selected = await open({
       multiple: true,
       directory: false,
-      filters: [{ name: 'Images', extensions: ['jpg', 'png', 'jpeg'] }]
+      filters: [{ name: 'Images', extensions: ['jpg', 'png', 'jpeg', 'gif', 'tif', 'tiff', 'bmp'] }]
     });
     if (Array.isArray(selected)) return selected;
     if (selected === null) return [];
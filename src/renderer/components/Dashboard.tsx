@@ -10,7 +10,7 @@ interface FileItem {
   originalSize: number;
   compressedSize: number;
   savedSize: number;
-  status: 'pending' | 'processing' | 'success' | 'error' | 'skipped';
+  status: 'pending' | 'processing' | 'success' | 'error' | 'skipped' | 'cancelled';
   progress: number;
   error?: string;
 }
@@ -40,11 +40,13 @@ const i18n = {
     done: 'Done',
     error: 'Error',
     skip: 'Skip',
+    cancelled: 'Cancelled',
     dropToAdd: '+ Drop to add',
     completed: 'completed',
     total: 'Total',
     savedLabel: 'Saved',
     clear: 'Clear',
+    cancel: 'Cancel',
     delete: 'Delete',
     errorTooltip: 'Compression failed',
     unsupportedFiles: 'Unsupported files skipped',
@@ -76,11 +78,13 @@ const i18n = {
     done: '完成',
     error: '错误',
     skip: '跳过',
+    cancelled: '已取消',
     dropToAdd: '+ 拖入添加',
     completed: '已完成',
     total: '总计',
     savedLabel: '节省',
     clear: '清空',
+    cancel: '取消',
     delete: '删除',
     errorTooltip: '压缩失败',
     unsupportedFiles: '已跳过不支持的文件',
@@ -149,7 +153,7 @@ const FileRow: React.FC<{
       };
       animationRef.current = requestAnimationFrame(animate);
       return () => { if (animationRef.current) cancelAnimationFrame(animationRef.current); };
-    } else if (['success', 'error', 'skipped'].includes(file.status)) {
+    } else if (['success', 'error', 'skipped', 'cancelled'].includes(file.status)) {
       const elapsed = Date.now() - startTimeRef.current;
       const remainingTime = Math.max(0, MIN_DURATION - elapsed);
       const timer = setTimeout(() => setDisplayProgress(100), remainingTime);
@@ -158,7 +162,7 @@ const FileRow: React.FC<{
   }, [file.status]);
 
   const getStatusContent = () => {
-    const labels = { pending: t.pending, success: t.done, error: t.error, skipped: t.skip };
+    const labels = { pending: t.pending, success: t.done, error: t.error, skipped: t.skip, cancelled: t.cancelled };
     
     // 错误状态：直接显示错误标签，带 tooltip
     if (file.status === 'error') {
@@ -375,7 +379,8 @@ const Dashboard: React.FC = () => {
 
     const unlisten = await api.onProgress((data) => {
       const result = data.result;
-      const filePath = result.filePath;
+      // With an output directory filePath is the written file, match on the source instead
+      const filePath = result.sourcePath || result.filePath;
       
       setFiles(prev => prev.map((f) => {
         if (f.path === filePath) {
@@ -384,8 +389,10 @@ const Dashboard: React.FC = () => {
             originalSize: result.originalSize || 0,
             compressedSize: result.compressedSize || 0,
             savedSize: result.savedBefore || 0,
-            status: result.status === 'success' ? 'success' : 
-                   result.status === 'skipped' ? 'skipped' : 'error',
+            // "preview" is a dry run that got as far as a successful compression
+            status: result.status === 'success' || result.status === 'preview' ? 'success' :
+                   result.status === 'cancelled' ? 'cancelled' :
+                   result.status.startsWith('skipped') ? 'skipped' : 'error',
             progress: 100,
             error: result.error,
           };
@@ -452,6 +459,13 @@ const Dashboard: React.FC = () => {
     }
   };
 
+  const handleCancel = async () => {
+    // Files still queued come back as "cancelled" progress events, which end the batch
+    try {
+      await api.cancelCompression();
+    } catch (e) { console.error(e); }
+  };
+
   const handleDeleteFile = (id: string) => {
     setFiles(prev => {
         const next = prev.filter(f => f.id !== id);
@@ -462,7 +476,7 @@ const Dashboard: React.FC = () => {
 
   const totalOriginal = files.reduce((sum, f) => sum + f.originalSize, 0);
   const totalSaved = files.reduce((sum, f) => sum + f.savedSize, 0);
-  const doneCount = files.filter(f => ['success', 'error', 'skipped'].includes(f.status)).length;
+  const doneCount = files.filter(f => ['success', 'error', 'skipped', 'cancelled'].includes(f.status)).length;
 
   return (
     <div className="app" onDragOver={handleDragOver} onDragLeave={handleDragLeave}>
@@ -567,7 +581,9 @@ const Dashboard: React.FC = () => {
             </>
           )}
         </div>
-        {files.length > 0 && (
+        {isProcessing ? (
+          <button className="btn btn-text" onClick={handleCancel}>{t.cancel}</button>
+        ) : files.length > 0 && (
           <button className="btn btn-text" onClick={handleClear}>{t.clear}</button>
         )}
       </footer>